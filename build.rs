@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Embeds every `migrations/<version>_<name>/{up,down}.sql` pair into the binary
+/// so the server can check and apply them without `diesel_cli`.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let migrations_dir = Path::new(&manifest_dir).join("migrations");
+
+    let mut dirs: Vec<_> = fs::read_dir(&migrations_dir)
+        .expect("Failed to read migrations directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+
+    let mut entries = String::new();
+    for dir in dirs {
+        let dir_name = dir.file_name().unwrap().to_str().unwrap().to_string();
+        let (version, name) = dir_name.split_once('_').unwrap_or((&dir_name, ""));
+        let version = version.replace('-', "");
+        let up_sql = dir.join("up.sql");
+        let down_sql = dir.join("down.sql");
+        entries.push_str(&format!(
+            "    EmbeddedMigration {{ version: {:?}, name: {:?}, up_sql: include_str!({:?}), down_sql: include_str!({:?}) }},\n",
+            version,
+            name,
+            up_sql.to_str().unwrap(),
+            down_sql.to_str().unwrap(),
+        ));
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(
+        Path::new(&out_dir).join("embedded_migrations.rs"),
+        format!("&[\n{}]\n", entries),
+    )
+    .expect("Failed to write embedded migrations");
+}
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'user'
+  CHECK (role IN ('user', 'moderator', 'admin'));
+
+CREATE TABLE role_changes (
+  id SERIAL PRIMARY KEY,
+  actor_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  target_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  old_role VARCHAR NOT NULL,
+  new_role VARCHAR NOT NULL,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
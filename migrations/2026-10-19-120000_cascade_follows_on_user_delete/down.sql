@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE follows
+  DROP CONSTRAINT follows_follower_id_fkey,
+  DROP CONSTRAINT follows_followed_id_fkey,
+  ADD CONSTRAINT follows_follower_id_fkey FOREIGN KEY (follower_id) REFERENCES users (id),
+  ADD CONSTRAINT follows_followed_id_fkey FOREIGN KEY (followed_id) REFERENCES users (id);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE follows
+  DROP CONSTRAINT follows_follower_id_fkey,
+  DROP CONSTRAINT follows_followed_id_fkey,
+  ADD CONSTRAINT follows_follower_id_fkey FOREIGN KEY (follower_id) REFERENCES users (id) ON DELETE CASCADE,
+  ADD CONSTRAINT follows_followed_id_fkey FOREIGN KEY (followed_id) REFERENCES users (id) ON DELETE CASCADE;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_lower_email_key;
+DROP INDEX users_lower_username_key;
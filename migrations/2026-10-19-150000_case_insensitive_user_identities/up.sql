@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Refuse to run while users exist that differ only by case, listing them so
+-- they can be renamed or removed before retrying.
+DO $$
+DECLARE
+  conflicts TEXT;
+BEGIN
+  SELECT string_agg(accounts, '; ') INTO conflicts FROM (
+    SELECT 'username ' || lower(username) || ': ' || string_agg(username || ' (id ' || id || ')', ', ' ORDER BY id) AS accounts
+    FROM users GROUP BY lower(username) HAVING count(*) > 1
+    UNION ALL
+    SELECT 'email ' || lower(email) || ': ' || string_agg(email || ' (id ' || id || ')', ', ' ORDER BY id)
+    FROM users GROUP BY lower(email) HAVING count(*) > 1
+  ) AS duplicates;
+  IF conflicts IS NOT NULL THEN
+    RAISE EXCEPTION 'users differ only by case: %', conflicts
+      USING HINT = 'Rename or delete the conflicting accounts, then run the migration again.';
+  END IF;
+END $$;
+
+CREATE UNIQUE INDEX users_lower_username_key ON users (lower(username));
+CREATE UNIQUE INDEX users_lower_email_key ON users (lower(email));
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE login_attempts;
+ALTER TABLE users DROP COLUMN locked_until;
+ALTER TABLE users DROP COLUMN failed_login_attempts;
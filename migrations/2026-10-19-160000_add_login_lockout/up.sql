@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN failed_login_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMPTZ;
+
+CREATE TABLE login_attempts (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  login VARCHAR NOT NULL,
+  ip VARCHAR,
+  succeeded BOOLEAN NOT NULL,
+  failure_reason VARCHAR,
+  attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_attempts_user_id_idx ON login_attempts (user_id, attempted_at);
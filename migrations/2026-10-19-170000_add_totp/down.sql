@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE mfa_challenges;
+DROP TABLE mfa_recovery_codes;
+ALTER TABLE users DROP COLUMN totp_last_used_step;
+ALTER TABLE users DROP COLUMN totp_enabled_at;
+ALTER TABLE users DROP COLUMN totp_secret;
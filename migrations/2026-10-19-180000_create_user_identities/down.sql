@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE oidc_link_requests;
+DROP TABLE oidc_auth_requests;
+DROP TABLE user_identities;
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+CREATE TABLE user_identities (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  issuer VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (issuer, subject)
+);
+
+CREATE INDEX user_identities_user_id_idx ON user_identities (user_id);
+
+-- In-flight authorization requests, consumed by the callback.
+CREATE TABLE oidc_auth_requests (
+  id SERIAL PRIMARY KEY,
+  state_hash VARCHAR NOT NULL UNIQUE,
+  provider VARCHAR NOT NULL,
+  nonce VARCHAR NOT NULL,
+  code_verifier VARCHAR NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Identities waiting for the owner of an existing account to confirm the
+-- link with their password.
+CREATE TABLE oidc_link_requests (
+  id SERIAL PRIMARY KEY,
+  token_hash VARCHAR NOT NULL UNIQUE,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  issuer VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
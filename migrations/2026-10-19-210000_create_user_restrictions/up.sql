@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE user_restrictions (
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  target_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  kind VARCHAR NOT NULL CHECK (kind IN ('block', 'mute')),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (user_id, target_id, kind)
+);
+
+CREATE INDEX user_restrictions_target_id_idx ON user_restrictions (target_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_bio_trgm_idx;
+DROP INDEX users_username_trgm_idx;
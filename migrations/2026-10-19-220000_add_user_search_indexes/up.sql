@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX users_bio_trgm_idx ON users USING GIN (bio gin_trgm_ops);
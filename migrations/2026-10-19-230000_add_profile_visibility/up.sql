@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN profile_visibility VARCHAR NOT NULL DEFAULT 'public';
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- Assets of deleted articles (or uploaded by deleted users) are kept with a
+-- NULL article_id until their file has been removed from storage.
+CREATE TABLE article_assets (
+  id SERIAL PRIMARY KEY,
+  article_id INTEGER REFERENCES articles (id) ON DELETE SET NULL,
+  uploader_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  key VARCHAR NOT NULL UNIQUE,
+  content_type VARCHAR NOT NULL,
+  size_bytes INTEGER NOT NULL,
+  is_cover BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX article_assets_article_id_idx ON article_assets (article_id);
+CREATE INDEX article_assets_uploader_id_idx ON article_assets (uploader_id);
+CREATE UNIQUE INDEX article_assets_cover_idx ON article_assets (article_id) WHERE is_cover;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE articles
+    DROP COLUMN word_count,
+    DROP COLUMN reading_time_minutes,
+    DROP COLUMN excerpt;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+ALTER TABLE articles
+    ADD COLUMN word_count INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN reading_time_minutes INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN excerpt VARCHAR NOT NULL DEFAULT '';
+
+-- Approximation for existing articles, with only the most common markup
+-- removed; `admin refresh-article-stats` computes the exact figures.
+WITH stripped AS (
+    SELECT id, btrim(regexp_replace(regexp_replace(body, '\]\([^)]*\)|<[^>]*>|[#*_`>|~\[\]]+', ' ', 'g'), '\s+', ' ', 'g')) AS text
+    FROM articles
+), counted AS (
+    SELECT id, text, COALESCE(array_length(regexp_split_to_array(NULLIF(text, ''), ' '), 1), 0) AS words
+    FROM stripped
+)
+UPDATE articles
+SET word_count = counted.words,
+    reading_time_minutes = CEIL(counted.words / 200.0),
+    excerpt = left(counted.text, 500)
+FROM counted
+WHERE articles.id = counted.id;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX articles_author_id_status_idx;
+DROP INDEX articles_publish_at_idx;
+DROP INDEX articles_published_at_idx;
+
+ALTER TABLE articles
+    DROP COLUMN publish_at,
+    DROP COLUMN published_at,
+    DROP COLUMN status;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE articles
+    ADD COLUMN status VARCHAR NOT NULL DEFAULT 'published'
+        CHECK (status IN ('draft', 'scheduled', 'published', 'archived')),
+    ADD COLUMN published_at TIMESTAMPTZ,
+    ADD COLUMN publish_at TIMESTAMPTZ;
+
+UPDATE articles SET published_at = created_at;
+
+ALTER TABLE articles
+    ADD CONSTRAINT articles_publish_at_check CHECK ((status = 'scheduled') = (publish_at IS NOT NULL));
+
+CREATE INDEX articles_published_at_idx ON articles (published_at) WHERE status = 'published';
+CREATE INDEX articles_publish_at_idx ON articles (publish_at) WHERE status = 'scheduled';
+CREATE INDEX articles_author_id_status_idx ON articles (author_id, status);
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- A snapshot of an article's text after each save, numbered from 1 per
+-- article. Old revisions are pruned, so numbers may start above 1.
+CREATE TABLE article_revisions (
+  id SERIAL PRIMARY KEY,
+  article_id INTEGER NOT NULL REFERENCES articles (id) ON DELETE CASCADE,
+  number INTEGER NOT NULL,
+  title VARCHAR NOT NULL,
+  description VARCHAR,
+  body VARCHAR NOT NULL,
+  editor_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (article_id, number)
+);
+
+CREATE INDEX article_revisions_editor_id_idx ON article_revisions (editor_id);
+
+INSERT INTO article_revisions (article_id, number, title, description, body, editor_id, created_at)
+SELECT id, 1, title, description, body, author_id, updated_at FROM articles;
@@ -0,0 +1,299 @@
+use crate::article;
+use crate::db::DbPool;
+use crate::user;
+use crate::user::db::{NewUserDTO, UserEntity};
+use clap::{Args, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use std::fmt;
+
+#[derive(Args)]
+pub struct AdminArgs {
+    /// How results are printed
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table, global = true)]
+    pub format: OutputFormat,
+    #[command(subcommand)]
+    pub command: AdminCommand,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Register a new user, with the password read as for `reset-password`
+    CreateUser {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        username: String,
+    },
+    /// Replace a user's password, read from `ADMIN_PASSWORD` or else from
+    /// stdin (prompted for without echo on a terminal)
+    ResetPassword {
+        #[arg(long)]
+        username: String,
+    },
+    /// Permanently delete a user along with their articles, favorites and follows
+    DeleteUser {
+        #[arg(long)]
+        username: String,
+    },
+    /// Prevent a user from logging in
+    DisableUser {
+        #[arg(long)]
+        username: String,
+    },
+    /// Allow a disabled user to log in again
+    EnableUser {
+        #[arg(long)]
+        username: String,
+    },
+    /// Clear failed logins and lift a lockout
+    UnlockUser {
+        #[arg(long)]
+        username: String,
+    },
+    /// Change a user's role (user, moderator or admin)
+    SetRole {
+        #[arg(long)]
+        username: String,
+        #[arg(long, value_parser = ["user", "moderator", "admin"])]
+        role: String,
+    },
+    /// Give an article to another author
+    TransferArticle {
+        #[arg(long)]
+        slug: String,
+        #[arg(long)]
+        to: String,
+    },
+    /// Delete an article
+    DeleteArticle {
+        #[arg(long)]
+        slug: String,
+    },
+    /// Re-tag every article tagged `from` with `into` and remove `from`
+    MergeTags {
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        into: String,
+    },
+    /// Recompute the word count, reading time and excerpt of every article
+    RefreshArticleStats,
+    /// Print row counts
+    Stats,
+}
+
+#[derive(Debug)]
+pub enum AdminError {
+    NotFound(String),
+    Database(diesel::result::Error),
+    InvalidPassword(String),
+    /// The arguments make no sense together, with why.
+    InvalidArguments(&'static str),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminError::NotFound(what) => write!(f, "{} not found", what),
+            AdminError::Database(e) => write!(f, "{}", e),
+            AdminError::InvalidPassword(reason) => write!(f, "{}", reason),
+            AdminError::InvalidArguments(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+impl From<diesel::result::Error> for AdminError {
+    fn from(e: diesel::result::Error) -> Self {
+        AdminError::Database(e)
+    }
+}
+
+/// Merging a tag into itself would remove it from every article.
+fn check_merge(from: &str, into: &str) -> Result<(), AdminError> {
+    if from == into {
+        return Err(AdminError::InvalidArguments("cannot merge a tag into itself"));
+    }
+    Ok(())
+}
+
+/// The password for a user, from `ADMIN_PASSWORD` or else from stdin, so
+/// that it stays out of the shell history and the process list.
+fn read_password() -> Result<String, AdminError> {
+    use std::io::IsTerminal;
+    let password = match std::env::var("ADMIN_PASSWORD") {
+        Ok(password) => password,
+        Err(_) if std::io::stdin().is_terminal() => rpassword::prompt_password("Password: ")
+            .map_err(|e| AdminError::InvalidPassword(format!("failed to read the password: {}", e)))?,
+        Err(_) => {
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(|e| AdminError::InvalidPassword(format!("failed to read the password: {}", e)))?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if password.is_empty() {
+        return Err(AdminError::InvalidPassword("the password is empty".to_string()));
+    }
+    Ok(password)
+}
+
+fn find_user(pool: &DbPool, username: &String) -> Result<UserEntity, AdminError> {
+    user::db::get_user_by_username(pool, username).map_err(|e| match e {
+        diesel::result::Error::NotFound => AdminError::NotFound(format!("user {}", username)),
+        e => AdminError::Database(e),
+    })
+}
+
+fn find_article(pool: &DbPool, slug: &str) -> Result<article::db::ArticleEntity, AdminError> {
+    article::db::get_by_slug(pool, slug.to_string()).map_err(|e| match e {
+        diesel::result::Error::NotFound => AdminError::NotFound(format!("article {}", slug)),
+        e => AdminError::Database(e),
+    })
+}
+
+fn user_json(user: &UserEntity) -> Value {
+    json!({
+        "id": user.id,
+        "username": user.username,
+        "email": user.email,
+        "email_verified": user.email_verified_at.is_some(),
+        "role": user.role,
+        "disabled": user.disabled_at.is_some(),
+        "locked_until": user.locked_until.map(|locked_until| locked_until.to_rfc3339()),
+    })
+}
+
+pub fn run(pool: &DbPool, args: AdminArgs) -> Result<(), AdminError> {
+    let output = match args.command {
+        AdminCommand::CreateUser { email, username } => {
+            let password = read_password()?;
+            let created = user::db::create(
+                pool,
+                NewUserDTO {
+                    email,
+                    username,
+                    password_hash: user::auth::hash_password(password),
+                },
+            )?;
+            user_json(&created)
+        }
+        AdminCommand::ResetPassword { username } => {
+            let found = find_user(pool, &username)?;
+            let password = read_password()?;
+            let updated = user::db::update_password(
+                pool,
+                &found.id,
+                user::auth::hash_password(password),
+            )?;
+            user_json(&updated)
+        }
+        AdminCommand::DeleteUser { username } => {
+            let found = find_user(pool, &username)?;
+            user::db::delete_user(pool, &found.id)?;
+            json!({ "deleted": found.username })
+        }
+        AdminCommand::DisableUser { username } => {
+            let found = find_user(pool, &username)?;
+            user_json(&user::db::set_disabled(pool, &found.id, true)?)
+        }
+        AdminCommand::EnableUser { username } => {
+            let found = find_user(pool, &username)?;
+            user_json(&user::db::set_disabled(pool, &found.id, false)?)
+        }
+        AdminCommand::UnlockUser { username } => {
+            let found = find_user(pool, &username)?;
+            user_json(&user::db::reset_failed_logins(pool, &found.id)?)
+        }
+        AdminCommand::SetRole { username, role } => {
+            let found = find_user(pool, &username)?;
+            let role_change = user::db::set_role(pool, None, &found.id, &role)?;
+            json!({
+                "username": found.username,
+                "old_role": role_change.old_role,
+                "new_role": role_change.new_role,
+            })
+        }
+        AdminCommand::TransferArticle { slug, to } => {
+            let found = find_article(pool, &slug)?;
+            let new_author = find_user(pool, &to)?;
+            let transferred = article::db::transfer(pool, found.id, new_author.id)?;
+            json!({ "slug": transferred.slug, "author": new_author.username })
+        }
+        AdminCommand::DeleteArticle { slug } => {
+            let found = find_article(pool, &slug)?;
+            article::db::delete(pool, found.id)?;
+            json!({ "deleted": found.slug })
+        }
+        AdminCommand::MergeTags { from, into } => {
+            check_merge(&from, &into)?;
+            let merged = article::db::merge_tags(pool, &from, &into)?;
+            json!({ "from": from, "into": into, "articles": merged })
+        }
+        AdminCommand::RefreshArticleStats => {
+            json!({ "refreshed": article::db::refresh_text_stats(pool)? })
+        }
+        AdminCommand::Stats => json!({
+            "users": user::db::count(pool)?,
+            "articles": article::db::count(pool)?,
+            "tags": article::db::count_tags(pool)?,
+            "follows": user::db::count_active_follows(pool)?,
+            "favorites": article::db::count_active_favorites(pool)?,
+        }),
+    };
+    match args.format {
+        OutputFormat::Json => println!("{}", output),
+        OutputFormat::Table => print_table(&output),
+    }
+    Ok(())
+}
+
+/// Prints a flat JSON object as a two-column key/value table.
+fn print_table(output: &Value) {
+    let rows: Vec<(String, String)> = match output.as_object() {
+        Some(object) => object
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+        None => vec![],
+    };
+    let key_width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    let value_width = rows.iter().map(|(_, value)| value.len()).max().unwrap_or(0);
+    let separator = format!("+-{}-+-{}-+", "-".repeat(key_width), "-".repeat(value_width));
+    println!("{}", separator);
+    for (key, value) in rows {
+        println!(
+            "| {:key_width$} | {:value_width$} |",
+            key,
+            value,
+            key_width = key_width,
+            value_width = value_width
+        );
+    }
+    println!("{}", separator);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_to_merge_a_tag_into_itself() {
+        assert!(matches!(check_merge("rust", "rust"), Err(AdminError::InvalidArguments(_))));
+        assert!(check_merge("rust", "rustlang").is_ok());
+    }
+}
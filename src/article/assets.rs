@@ -0,0 +1,155 @@
+//! Images attached to articles, stored through the `BlobStore` like avatars
+//! but kept as uploaded. Each user has a storage quota over the assets they
+//! uploaded.
+//!
+//! Deleting an article (or the account owning it) only detaches its assets
+//! in the database. A deletion through the API then removes the files of the
+//! assets it detached; a periodic sweep removes the rest (e.g. after `admin`
+//! commands, or when that removal failed).
+
+use super::db;
+use super::errors::ArticleError;
+use crate::blob::BlobStore;
+use crate::db::DbPool;
+use crate::errors::internal_server_error;
+use crate::upload::UploadedFile;
+use actix_web::web;
+use image::ImageFormat;
+use juniper::{FieldResult, IntoFieldError};
+use std::sync::Arc;
+use std::time::Duration;
+
+const ACCEPTED_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::WebP,
+    ImageFormat::Gif,
+];
+
+/// Detached assets removed per batch when purging.
+const PURGE_BATCH: i64 = 100;
+
+fn env_bytes(key: &str, default: i64) -> i64 {
+    std::env::var(key)
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(default)
+}
+
+/// The largest accepted asset, from `ASSET_MAX_BYTES` (5 MiB by default).
+fn max_bytes() -> i64 {
+    env_bytes("ASSET_MAX_BYTES", 5 * 1024 * 1024)
+}
+
+/// What a user may store in total, from `ASSET_QUOTA_BYTES` (100 MiB by default).
+pub fn quota_bytes() -> i64 {
+    env_bytes("ASSET_QUOTA_BYTES", 100 * 1024 * 1024)
+}
+
+/// Checks an upload and returns the format sniffed from its contents, which
+/// must agree with the declared content type.
+pub fn check(file: &UploadedFile) -> Result<ImageFormat, ArticleError> {
+    let declared = file
+        .content_type
+        .as_deref()
+        .and_then(ImageFormat::from_mime_type);
+    let format = match (declared, image::guess_format(&file.bytes).ok()) {
+        (Some(declared), Some(sniffed)) if declared == sniffed && ACCEPTED_FORMATS.contains(&sniffed) => sniffed,
+        _ => return Err(ArticleError::InvalidAsset("Assets must be PNG, JPEG, WebP or GIF images")),
+    };
+    if file.bytes.len() as i64 > max_bytes() {
+        return Err(ArticleError::InvalidAsset("Asset is too large"));
+    }
+    Ok(format)
+}
+
+/// Checks that `size` more bytes fit in the user's quota, to turn down an
+/// upload before storing its file. `db::create_asset` checks again when
+/// recording it.
+pub fn check_quota(pool: &DbPool, user_id: i32, size: i64) -> FieldResult<()> {
+    let used = db::get_asset_usage(pool, user_id).map_err(internal_server_error)?;
+    if used + size > quota_bytes() {
+        return Err(ArticleError::QuotaExceeded.into_field_error());
+    }
+    Ok(())
+}
+
+pub fn key(article_id: i32, format: ImageFormat) -> String {
+    format!(
+        "articles/{}/{}.{}",
+        article_id,
+        uuid::Uuid::new_v4(),
+        format.extensions_str()[0]
+    )
+}
+
+/// Removes the files of detached assets, then their rows. Returns how many
+/// were removed; failures are logged and retried by the next purge.
+pub async fn purge_detached(pool: &DbPool, blob_store: &dyn BlobStore) -> usize {
+    let mut purged = 0;
+    loop {
+        let detached = match blocking(pool, |pool| db::get_detached_assets(pool, PURGE_BATCH)).await {
+            Ok(detached) => detached,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to list detached assets");
+                return purged;
+            }
+        };
+        let batch_size = detached.len();
+        let removed = remove(pool, blob_store, detached).await;
+        purged += removed;
+        if removed < batch_size || (batch_size as i64) < PURGE_BATCH {
+            return purged;
+        }
+    }
+}
+
+/// Removes the files then the rows of the given detached assets, as
+/// `(id, key)`, stopping at the first failure. Returns how many were
+/// removed; the others are left to the sweep.
+pub async fn remove(pool: &DbPool, blob_store: &dyn BlobStore, detached: Vec<(i32, String)>) -> usize {
+    let mut removed = 0;
+    for (asset_id, key) in detached {
+        if let Err(e) = blob_store.delete(&key).await {
+            tracing::warn!(error = %e, key = %key, "failed to delete asset file");
+            break;
+        }
+        if let Err(e) = blocking(pool, move |pool| db::delete_asset(pool, asset_id)).await {
+            tracing::error!(error = %e, asset_id, "failed to delete asset");
+            break;
+        }
+        removed += 1;
+    }
+    removed
+}
+
+/// Runs a query on the blocking thread pool, off the async workers.
+async fn blocking<T, F>(pool: &DbPool, query: F) -> Result<T, String>
+where
+    F: FnOnce(&DbPool) -> diesel::QueryResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    match web::block(move || query(&pool)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Purges detached assets every `ASSET_SWEEP_SECONDS` (300 by default, at
+/// least 1).
+pub async fn sweep(pool: DbPool, blob_store: Arc<dyn BlobStore>) {
+    let seconds = std::env::var("ASSET_SWEEP_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(300)
+        .max(1);
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(seconds));
+    loop {
+        interval.tick().await;
+        let purged = purge_detached(&pool, blob_store.as_ref()).await;
+        if purged > 0 {
+            tracing::info!(purged, "detached assets purged");
+        }
+    }
+}
@@ -1,9 +1,15 @@
-use super::resolvers::NewArticle;
-use crate::db::DbPool;
+use super::model::ArticleStatus;
+use super::resolvers::{NewArticle, UpdateArticle};
+use super::revisions;
+use super::text;
+use crate::db::{lower, DbPool};
+use crate::db_schema::article_assets;
+use crate::db_schema::article_revisions;
 use crate::db_schema::articles;
 use crate::db_schema::tag_article;
 use crate::db_schema::tags;
 use crate::db_schema::user_favorites_article;
+use crate::db_schema::users;
 use chrono::{DateTime, Utc};
 use diesel::pg::upsert::*;
 use diesel::prelude::*;
@@ -20,6 +26,12 @@ pub struct ArticleEntity {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub author_id: i32,
+    pub word_count: i32,
+    pub reading_time_minutes: i32,
+    pub excerpt: String,
+    pub status: String,
+    pub published_at: Option<DateTime<Utc>>,
+    pub publish_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Queryable, PartialEq, Insertable)]
@@ -53,68 +65,332 @@ pub struct NewArticleDTO {
     pub author_id: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub word_count: i32,
+    pub reading_time_minutes: i32,
+    pub excerpt: String,
+    pub status: String,
+    pub published_at: Option<DateTime<Utc>>,
 }
 
-fn new_article_dto_from_new_article(new_article: &NewArticle, author_id: i32) -> NewArticleDTO {
+#[derive(AsChangeset)]
+#[table_name = "articles"]
+pub struct ArticleUpdateDTO {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub body: Option<String>,
+    pub slug: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    pub word_count: Option<i32>,
+    pub reading_time_minutes: Option<i32>,
+    pub excerpt: Option<String>,
+}
+
+/// The figures stored along with a body, see `text::stats`.
+#[derive(AsChangeset)]
+#[table_name = "articles"]
+pub struct TextStatsDTO {
+    pub word_count: i32,
+    pub reading_time_minutes: i32,
+    pub excerpt: String,
+}
+
+/// The text of an article as saved by one create, update or restore.
+#[derive(Queryable)]
+pub struct ArticleRevisionEntity {
+    pub number: i32,
+    pub title: String,
+    pub description: Option<String>,
+    pub body: String,
+    pub editor_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+const ARTICLE_REVISION_COLUMNS: (
+    article_revisions::number,
+    article_revisions::title,
+    article_revisions::description,
+    article_revisions::body,
+    article_revisions::editor_id,
+    article_revisions::created_at,
+) = (
+    article_revisions::number,
+    article_revisions::title,
+    article_revisions::description,
+    article_revisions::body,
+    article_revisions::editor_id,
+    article_revisions::created_at,
+);
+
+#[derive(Insertable)]
+#[table_name = "article_revisions"]
+pub struct NewArticleRevisionDTO<'a> {
+    pub article_id: i32,
+    pub number: i32,
+    pub title: &'a str,
+    pub description: Option<&'a str>,
+    pub body: &'a str,
+    pub editor_id: i32,
+}
+
+/// A file attached to an article. `article_id` is `None` once the article
+/// is gone and the file waits to be removed from storage.
+#[derive(Queryable)]
+pub struct ArticleAssetEntity {
+    pub id: i32,
+    pub article_id: Option<i32>,
+    pub key: String,
+    pub content_type: String,
+    pub size_bytes: i32,
+    pub is_cover: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+const ARTICLE_ASSET_COLUMNS: (
+    article_assets::id,
+    article_assets::article_id,
+    article_assets::key,
+    article_assets::content_type,
+    article_assets::size_bytes,
+    article_assets::is_cover,
+    article_assets::created_at,
+) = (
+    article_assets::id,
+    article_assets::article_id,
+    article_assets::key,
+    article_assets::content_type,
+    article_assets::size_bytes,
+    article_assets::is_cover,
+    article_assets::created_at,
+);
+
+#[derive(Insertable)]
+#[table_name = "article_assets"]
+pub struct NewArticleAssetDTO {
+    pub article_id: i32,
+    pub uploader_id: i32,
+    pub key: String,
+    pub content_type: String,
+    pub size_bytes: i32,
+    pub is_cover: bool,
+}
+
+fn insert_tags(conn: &PgConnection, given_article_id: i32, tag_list: &[String]) -> QueryResult<()> {
+    use diesel::insert_into;
+    use crate::db_schema::tags::dsl::*;
+    let insertable_tags: Vec<TagEntity> = tag_list
+        .iter()
+        .map(|given_tag| TagEntity {
+            tag: given_tag.to_owned(),
+        })
+        .collect();
+    insert_into(tags)
+        .values(&insertable_tags)
+        .on_conflict(on_constraint("tags_pkey"))
+        .do_nothing()
+        .execute(conn)?;
+
+    let insertable_article_tags: Vec<TagArticleEntity> = tag_list
+        .iter()
+        .map(|given_tag| TagArticleEntity {
+            tag: given_tag.to_owned(),
+            article_id: given_article_id,
+        })
+        .collect();
+    use crate::db_schema::tag_article::dsl::*;
+    insert_into(tag_article)
+        .values(&insertable_article_tags)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records the current text of an article as its next revision, then prunes
+/// the revisions beyond the retention limit. The article row is locked
+/// before numbering, so concurrent saves get consecutive numbers instead of
+/// the same one.
+fn insert_revision(conn: &PgConnection, article: &ArticleEntity, given_editor_id: i32) -> QueryResult<()> {
+    articles::table
+        .filter(articles::id.eq(article.id))
+        .select(articles::id)
+        .for_update()
+        .first::<i32>(conn)?;
+    use crate::db_schema::article_revisions::dsl::*;
+    let last_number = article_revisions
+        .filter(article_id.eq(article.id))
+        .select(diesel::dsl::max(number))
+        .first::<Option<i32>>(conn)?;
+    let next_number = last_number.unwrap_or(0) + 1;
+    diesel::insert_into(article_revisions)
+        .values(&NewArticleRevisionDTO {
+            article_id: article.id,
+            number: next_number,
+            title: &article.title,
+            description: article.description.as_deref(),
+            body: &article.body,
+            editor_id: given_editor_id,
+        })
+        .execute(conn)?;
+    if let Some(keep) = revisions::keep() {
+        diesel::delete(
+            article_revisions
+                .filter(article_id.eq(article.id))
+                .filter(number.le(next_number - keep)),
+        )
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+fn new_article_dto_from_new_article(
+    new_article: &NewArticle,
+    author_id: i32,
+    status: ArticleStatus,
+) -> NewArticleDTO {
+    let stats = text::stats(&new_article.body);
+    let now = Utc::now();
     NewArticleDTO {
         title: new_article.title.clone(),
         description: new_article.description.clone(),
         body: new_article.body.clone(),
         slug: slugify!(new_article.title.as_str()),
         author_id,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
+        created_at: now,
+        updated_at: now,
+        word_count: stats.word_count,
+        reading_time_minutes: stats.reading_time_minutes,
+        excerpt: stats.excerpt,
+        status: status.as_str().to_string(),
+        published_at: (status == ArticleStatus::Published).then_some(now),
     }
 }
 
+/// Creates an article, either published right away or as a draft.
+#[tracing::instrument(skip_all, fields(author_id = given_author_id, status = given_status.as_str()))]
 pub fn create(
     pool: &DbPool,
     new_article: NewArticle,
     given_author_id: i32,
+    given_status: ArticleStatus,
 ) -> QueryResult<ArticleEntity> {
     use crate::db_schema::articles::dsl::*;
     use diesel::insert_into;
     let conn = pool.get().unwrap();
     let created_article_entity = conn.transaction::<_, diesel::result::Error, _>(|| {
-        let new_article_dto = new_article_dto_from_new_article(&new_article, given_author_id);
+        let new_article_dto = new_article_dto_from_new_article(&new_article, given_author_id, given_status);
         let created_article_entity = insert_into(articles)
             .values(&new_article_dto)
             .get_result::<ArticleEntity>(&conn)?;
-        let returned_article_id = created_article_entity.id;
+        insert_revision(&conn, &created_article_entity, given_author_id)?;
         if let Some(tag_list) = &new_article.tag_list {
-            use crate::db_schema::tags::dsl::*;
-            let insertable_tags: Vec<TagEntity> = tag_list
-                .iter()
-                .map(|given_tag| TagEntity {
-                    tag: given_tag.to_owned(),
-                })
-                .collect();
-            insert_into(tags)
-                .values(&insertable_tags)
-                .on_conflict(on_constraint("tags_pkey"))
-                .do_nothing()
-                .execute(&conn)?;
-
-            let insertable_article_tags: Vec<TagArticleEntity> = tag_list
-                .iter()
-                .map(|given_tag| TagArticleEntity {
-                    tag: given_tag.to_owned(),
-                    article_id: returned_article_id,
-                })
-                .collect();
-            use crate::db_schema::tag_article::dsl::*;
-            let result = insert_into(tag_article)
-                .values(&insertable_article_tags)
-                .execute(&conn);
-            if let Err(e) = result {
-                return Err(e);
-            }
+            insert_tags(&conn, created_article_entity.id, tag_list)?;
         }
         Ok(created_article_entity)
     })?;
     Ok(created_article_entity)
 }
 
+#[tracing::instrument(skip_all, fields(article_id = given_id, editor_id = given_editor_id))]
+pub fn update(
+    pool: &DbPool,
+    given_id: i32,
+    update_article: UpdateArticle,
+    given_editor_id: i32,
+) -> QueryResult<ArticleEntity> {
+    use crate::db_schema::articles::dsl::*;
+    let conn = pool.get().unwrap();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let stats = update_article.body.as_deref().map(text::stats);
+        let article_update_dto = ArticleUpdateDTO {
+            slug: update_article
+                .title
+                .as_ref()
+                .map(|given_title| slugify!(given_title.as_str())),
+            title: update_article.title,
+            description: update_article.description,
+            body: update_article.body,
+            updated_at: Utc::now(),
+            word_count: stats.as_ref().map(|stats| stats.word_count),
+            reading_time_minutes: stats.as_ref().map(|stats| stats.reading_time_minutes),
+            excerpt: stats.map(|stats| stats.excerpt),
+        };
+        let updated_article_entity = diesel::update(articles.filter(id.eq(given_id)))
+            .set(&article_update_dto)
+            .get_result::<ArticleEntity>(&conn)?;
+        insert_revision(&conn, &updated_article_entity, given_editor_id)?;
+        if let Some(tag_list) = &update_article.tag_list {
+            use crate::db_schema::tag_article::dsl::*;
+            diesel::delete(tag_article.filter(article_id.eq(given_id))).execute(&conn)?;
+            insert_tags(&conn, given_id, tag_list)?;
+        }
+        Ok(updated_article_entity)
+    })
+}
+
+/// Puts back the text of a revision, which is recorded as a new revision.
+#[tracing::instrument(skip_all, fields(article_id = given_id, number = given_number, editor_id = given_editor_id))]
+pub fn restore_revision(
+    pool: &DbPool,
+    given_id: i32,
+    given_number: i32,
+    given_editor_id: i32,
+) -> QueryResult<ArticleEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let revision = article_revisions::table
+            .filter(article_revisions::article_id.eq(given_id))
+            .filter(article_revisions::number.eq(given_number))
+            .select(ARTICLE_REVISION_COLUMNS)
+            .first::<ArticleRevisionEntity>(&conn)?;
+        let stats = text::stats(&revision.body);
+        use crate::db_schema::articles::dsl::*;
+        let restored = diesel::update(articles.filter(id.eq(given_id)))
+            .set((
+                slug.eq(slugify!(revision.title.as_str())),
+                title.eq(&revision.title),
+                description.eq(&revision.description),
+                body.eq(&revision.body),
+                updated_at.eq(Utc::now()),
+                &TextStatsDTO {
+                    word_count: stats.word_count,
+                    reading_time_minutes: stats.reading_time_minutes,
+                    excerpt: stats.excerpt,
+                },
+            ))
+            .get_result::<ArticleEntity>(&conn)?;
+        insert_revision(&conn, &restored, given_editor_id)?;
+        Ok(restored)
+    })
+}
+
+/// The kept revisions of an article, the latest first.
+#[tracing::instrument(skip_all, fields(article_id = given_article_id))]
+pub fn get_revisions(pool: &DbPool, given_article_id: i32) -> QueryResult<Vec<ArticleRevisionEntity>> {
+    use crate::db_schema::article_revisions::dsl::*;
+    let conn = pool.get().unwrap();
+    article_revisions
+        .filter(article_id.eq(given_article_id))
+        .order_by(number.desc())
+        .select(ARTICLE_REVISION_COLUMNS)
+        .load::<ArticleRevisionEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(article_id = given_article_id, number = given_number))]
+pub fn get_revision(
+    pool: &DbPool,
+    given_article_id: i32,
+    given_number: i32,
+) -> QueryResult<Option<ArticleRevisionEntity>> {
+    use crate::db_schema::article_revisions::dsl::*;
+    let conn = pool.get().unwrap();
+    article_revisions
+        .filter(article_id.eq(given_article_id))
+        .filter(number.eq(given_number))
+        .select(ARTICLE_REVISION_COLUMNS)
+        .first::<ArticleRevisionEntity>(&conn)
+        .optional()
+}
+
+#[tracing::instrument(skip_all, fields(user_id = given_user_id, article_id = given_article_id))]
 pub fn get_user_favorites_article(
     pool: &DbPool,
     given_user_id: i32,
@@ -133,16 +409,13 @@ pub fn get_user_favorites_article(
     match favorites {
         Err(diesel::result::Error::NotFound) => {
             use diesel::insert_into;
-            let exec_result = insert_into(user_favorites_article)
+            insert_into(user_favorites_article)
                 .values(&UserFavoritesArticle {
                     user_id: given_user_id,
                     article_id: given_article_id,
                     active: false,
                 })
-                .execute(&conn);
-            if let Err(e) = exec_result {
-                return Err(e);
-            }
+                .execute(&conn)?;
             Ok(false)
         }
         Ok(x) => Ok(x),
@@ -150,6 +423,7 @@ pub fn get_user_favorites_article(
     }
 }
 
+#[tracing::instrument(skip_all, fields(slug = %given_slug))]
 pub fn get_by_slug(pool: &DbPool, given_slug: String) -> QueryResult<ArticleEntity> {
     use crate::db_schema::articles::dsl::*;
     let conn = pool.get().unwrap();
@@ -159,11 +433,31 @@ pub fn get_by_slug(pool: &DbPool, given_slug: String) -> QueryResult<ArticleEnti
     Ok(entity)
 }
 
+#[tracing::instrument(skip_all, fields(article_id = given_id))]
+pub fn get_by_id(pool: &DbPool, given_id: i32) -> QueryResult<ArticleEntity> {
+    use crate::db_schema::articles::dsl::*;
+    let conn = pool.get().unwrap();
+    articles.filter(id.eq(given_id)).first::<ArticleEntity>(&conn)
+}
+
 use super::resolvers::{ArticlesOptions, ArticlesPage};
-pub fn get_articles(pool: &DbPool, options: ArticlesOptions) -> QueryResult<ArticlesPage> {
+/// Articles matching the options, without those of authors hidden from the
+/// viewer (see `user::db::get_hidden_author_ids`).
+#[tracing::instrument(skip_all, fields(tag = ?options.tag, author = ?options.author, favorited = ?options.favorited, viewer_id = ?viewer_id))]
+pub fn get_articles(pool: &DbPool, options: ArticlesOptions, viewer_id: Option<i32>) -> QueryResult<ArticlesPage> {
+    let hidden_author_ids = match viewer_id {
+        Some(viewer_id) => crate::user::db::get_hidden_author_ids(pool, &viewer_id)?,
+        None => Vec::new(),
+    };
     let conn = pool.get().unwrap();
     use diesel::pg::Pg;
-    let mut query = crate::db_schema::articles::table.into_boxed::<Pg>();
+    let mut query = crate::db_schema::articles::table
+        .filter(crate::db_schema::articles::status.eq(ArticleStatus::Published.as_str()))
+        .into_boxed::<Pg>();
+    if !hidden_author_ids.is_empty() {
+        use crate::db_schema::articles::dsl::*;
+        query = query.filter(author_id.ne_all(hidden_author_ids));
+    }
     if let Some(given_tag) = options.tag {
         use crate::db_schema::tag_article::dsl::*;
         let article_ids = tag_article
@@ -177,7 +471,7 @@ pub fn get_articles(pool: &DbPool, options: ArticlesOptions) -> QueryResult<Arti
         use crate::db_schema::users::dsl::id;
         use crate::db_schema::users::dsl::*;
         let given_author_id = users
-            .filter(username.eq(given_author))
+            .filter(lower(username).eq(lower(given_author)))
             .select(id)
             .first::<i32>(&conn)?;
         use crate::db_schema::articles::dsl::*;
@@ -187,7 +481,7 @@ pub fn get_articles(pool: &DbPool, options: ArticlesOptions) -> QueryResult<Arti
         use crate::db_schema::user_favorites_article::dsl::*;
         use crate::db_schema::users::dsl::*;
         let given_favorited_by_id = users
-            .filter(username.eq(given_favorited_by))
+            .filter(lower(username).eq(lower(given_favorited_by)))
             .select(id)
             .first::<i32>(&conn)?;
         let article_ids = user_favorites_article
@@ -196,11 +490,11 @@ pub fn get_articles(pool: &DbPool, options: ArticlesOptions) -> QueryResult<Arti
             .load::<i32>(&conn)?;
         query = query.filter(crate::db_schema::articles::dsl::id.eq_any(article_ids));
     }
-    use crate::db_schema::articles::dsl::created_at;
+    use crate::db_schema::articles::dsl::published_at;
     let articles = query
         .offset(options.offset.unwrap_or(0) as i64)
         .limit(options.limit.unwrap_or(20) as i64)
-        .order_by(created_at.asc())
+        .order_by(published_at.asc())
         .load::<ArticleEntity>(&conn)?;
 
     let count = articles.len() as i32;
@@ -213,19 +507,107 @@ pub fn get_articles(pool: &DbPool, options: ArticlesOptions) -> QueryResult<Arti
 
 use super::resolvers::FeedOptions;
 
+/// The author's drafts and scheduled articles, the most recently edited first.
+#[tracing::instrument(skip_all, fields(author_id = given_author_id))]
+pub fn get_drafts(pool: &DbPool, given_author_id: i32, options: FeedOptions) -> QueryResult<ArticlesPage> {
+    use crate::db_schema::articles::dsl::*;
+    let conn = pool.get().unwrap();
+    let drafts = articles
+        .filter(author_id.eq(given_author_id))
+        .filter(status.eq_any([ArticleStatus::Draft.as_str(), ArticleStatus::Scheduled.as_str()]))
+        .offset(options.offset.unwrap_or(0) as i64)
+        .limit(options.limit.unwrap_or(20) as i64)
+        .order_by((updated_at.desc(), id.desc()))
+        .load::<ArticleEntity>(&conn)?;
+
+    let count = drafts.len() as i32;
+
+    Ok(ArticlesPage {
+        articles: drafts,
+        articles_count: count,
+    })
+}
+
+/// Publishes an article now. An article published before (and archived
+/// since) keeps its first publication date.
+#[tracing::instrument(skip_all, fields(article_id = given_id))]
+pub fn publish(pool: &DbPool, given_id: i32) -> QueryResult<ArticleEntity> {
+    use crate::db_schema::articles::dsl::*;
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Nullable, Timestamptz};
+    let conn = pool.get().unwrap();
+    diesel::update(articles.filter(id.eq(given_id)))
+        .set((
+            status.eq(ArticleStatus::Published.as_str()),
+            published_at.eq(sql::<Nullable<Timestamptz>>("COALESCE(published_at, NOW())")),
+            publish_at.eq(None::<DateTime<Utc>>),
+        ))
+        .get_result::<ArticleEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(article_id = given_id, publish_at = %given_publish_at))]
+pub fn schedule(pool: &DbPool, given_id: i32, given_publish_at: DateTime<Utc>) -> QueryResult<ArticleEntity> {
+    use crate::db_schema::articles::dsl::*;
+    let conn = pool.get().unwrap();
+    diesel::update(articles.filter(id.eq(given_id)))
+        .set((
+            status.eq(ArticleStatus::Scheduled.as_str()),
+            publish_at.eq(Some(given_publish_at)),
+        ))
+        .get_result::<ArticleEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(article_id = given_id))]
+pub fn archive(pool: &DbPool, given_id: i32) -> QueryResult<ArticleEntity> {
+    use crate::db_schema::articles::dsl::*;
+    let conn = pool.get().unwrap();
+    diesel::update(articles.filter(id.eq(given_id)))
+        .set((
+            status.eq(ArticleStatus::Archived.as_str()),
+            publish_at.eq(None::<DateTime<Utc>>),
+        ))
+        .get_result::<ArticleEntity>(&conn)
+}
+
+/// Publishes the scheduled articles whose time has come, dated from when
+/// they were due. Returns their ids.
+#[tracing::instrument(skip_all)]
+pub fn publish_due(pool: &DbPool) -> QueryResult<Vec<i32>> {
+    use crate::db_schema::articles::dsl::*;
+    use diesel::dsl::{now, sql};
+    use diesel::sql_types::{Nullable, Timestamptz};
+    let conn = pool.get().unwrap();
+    diesel::update(
+        articles
+            .filter(status.eq(ArticleStatus::Scheduled.as_str()))
+            .filter(publish_at.le(now)),
+    )
+    .set((
+        status.eq(ArticleStatus::Published.as_str()),
+        published_at.eq(sql::<Nullable<Timestamptz>>("COALESCE(published_at, publish_at)")),
+        publish_at.eq(None::<DateTime<Utc>>),
+    ))
+    .returning(id)
+    .get_results::<i32>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = user_id))]
 pub fn get_feed(pool: &DbPool, user_id: i32, options: FeedOptions) -> QueryResult<ArticlesPage> {
+    let hidden_author_ids = crate::user::db::get_hidden_author_ids(pool, &user_id)?;
     let conn = pool.get().unwrap();
 
     use crate::db_schema::articles::dsl::*;
     use crate::db_schema::follows::dsl::*;
 
     let followed_authors_ids = follows.filter(follower_id.eq(user_id)).select(followed_id);
-    let query = articles.filter(author_id.eq_any(followed_authors_ids));
-    use crate::db_schema::articles::dsl::created_at;
+    let query = articles
+        .filter(status.eq(ArticleStatus::Published.as_str()))
+        .filter(author_id.eq_any(followed_authors_ids))
+        .filter(author_id.ne_all(hidden_author_ids));
     let found_articles = query
         .offset(options.offset.unwrap_or(0) as i64)
         .limit(options.limit.unwrap_or(20) as i64)
-        .order_by(created_at.asc())
+        .order_by(published_at.asc())
         .load::<ArticleEntity>(&conn)?;
 
     let count = found_articles.len() as i32;
@@ -236,12 +618,280 @@ pub fn get_feed(pool: &DbPool, user_id: i32, options: FeedOptions) -> QueryResul
     })
 }
 
-pub fn delete(pool: &DbPool, given_id: i32) -> QueryResult<()> {
+/// Deletes an article and returns the assets it detached, as `(id, key)`.
+#[tracing::instrument(skip_all, fields(article_id = given_id))]
+pub fn delete(pool: &DbPool, given_id: i32) -> QueryResult<Vec<(i32, String)>> {
     let conn = pool.get().unwrap();
 
     use crate::db_schema::articles::dsl::*;
 
-    diesel::delete(articles.filter(id.eq(given_id))).execute(&conn)?;
+    conn.transaction(|| {
+        let detached = article_assets::table
+            .filter(article_assets::article_id.eq(given_id))
+            .select((article_assets::id, article_assets::key))
+            .for_update()
+            .load::<(i32, String)>(&conn)?;
+        diesel::delete(articles.filter(id.eq(given_id))).execute(&conn)?;
+        Ok(detached)
+    })
+}
 
-    Ok(())
+#[tracing::instrument(skip_all, fields(article_id = given_id, new_author_id = new_author_id))]
+pub fn transfer(pool: &DbPool, given_id: i32, new_author_id: i32) -> QueryResult<ArticleEntity> {
+    let conn = pool.get().unwrap();
+
+    use crate::db_schema::articles::dsl::*;
+
+    diesel::update(articles.filter(id.eq(given_id)))
+        .set(author_id.eq(new_author_id))
+        .get_result::<ArticleEntity>(&conn)
+}
+
+/// Re-tags every article tagged `from_tag` with `into_tag` and drops `from_tag`.
+/// Returns the number of articles that were re-tagged.
+#[tracing::instrument(skip_all, fields(from_tag = %from_tag, into_tag = %into_tag))]
+pub fn merge_tags(pool: &DbPool, from_tag: &str, into_tag: &str) -> QueryResult<usize> {
+    use diesel::insert_into;
+    // Merging a tag into itself would delete it once its rows were copied.
+    if from_tag == into_tag {
+        return Ok(0);
+    }
+    let conn = pool.get().unwrap();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        use crate::db_schema::tag_article::dsl::*;
+        let article_ids = tag_article
+            .filter(tag.eq(from_tag))
+            .select(article_id)
+            .load::<i32>(&conn)?;
+        insert_into(crate::db_schema::tags::table)
+            .values(&TagEntity {
+                tag: into_tag.to_owned(),
+            })
+            .on_conflict(on_constraint("tags_pkey"))
+            .do_nothing()
+            .execute(&conn)?;
+        let insertable_article_tags: Vec<TagArticleEntity> = article_ids
+            .iter()
+            .map(|given_article_id| TagArticleEntity {
+                tag: into_tag.to_owned(),
+                article_id: *given_article_id,
+            })
+            .collect();
+        insert_into(tag_article)
+            .values(&insertable_article_tags)
+            .on_conflict(on_constraint("tag_article_pkey"))
+            .do_nothing()
+            .execute(&conn)?;
+        diesel::delete(tag_article.filter(tag.eq(from_tag))).execute(&conn)?;
+        diesel::delete(crate::db_schema::tags::table.filter(crate::db_schema::tags::tag.eq(from_tag)))
+            .execute(&conn)?;
+        Ok(article_ids.len())
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn count(pool: &DbPool) -> QueryResult<i64> {
+    let conn = pool.get().unwrap();
+    crate::db_schema::articles::table
+        .count()
+        .get_result::<i64>(&conn)
+}
+
+/// Recomputes the stored text figures of every article from its body,
+/// leaving `updated_at` alone. Returns how many articles were updated.
+#[tracing::instrument(skip_all)]
+pub fn refresh_text_stats(pool: &DbPool) -> QueryResult<usize> {
+    use crate::db_schema::articles::dsl::*;
+    const BATCH: i64 = 500;
+    let conn = pool.get().unwrap();
+    let mut refreshed = 0;
+    let mut last_id = 0;
+    loop {
+        let batch = articles
+            .filter(id.gt(last_id))
+            .order_by(id.asc())
+            .limit(BATCH)
+            .select((id, body))
+            .load::<(i32, String)>(&conn)?;
+        for (article_id, article_body) in &batch {
+            let stats = text::stats(article_body);
+            diesel::update(articles.filter(id.eq(article_id)))
+                .set(&TextStatsDTO {
+                    word_count: stats.word_count,
+                    reading_time_minutes: stats.reading_time_minutes,
+                    excerpt: stats.excerpt,
+                })
+                .execute(&conn)?;
+            refreshed += 1;
+        }
+        match batch.last() {
+            Some((article_id, _)) if batch.len() as i64 == BATCH => last_id = *article_id,
+            _ => return Ok(refreshed),
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn count_tags(pool: &DbPool) -> QueryResult<i64> {
+    let conn = pool.get().unwrap();
+    crate::db_schema::tags::table.count().get_result::<i64>(&conn)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn count_active_favorites(pool: &DbPool) -> QueryResult<i64> {
+    let conn = pool.get().unwrap();
+    use crate::db_schema::user_favorites_article::dsl::*;
+    user_favorites_article
+        .filter(active.eq(true))
+        .count()
+        .get_result::<i64>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(author_id = given_author_id))]
+pub fn get_by_author(pool: &DbPool, given_author_id: i32) -> QueryResult<Vec<ArticleEntity>> {
+    use crate::db_schema::articles::dsl::*;
+    let conn = pool.get().unwrap();
+    articles
+        .filter(author_id.eq(given_author_id))
+        .order_by(created_at.asc())
+        .load::<ArticleEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(article_id = given_article_id))]
+pub fn get_tags(pool: &DbPool, given_article_id: i32) -> QueryResult<Vec<String>> {
+    use crate::db_schema::tag_article::dsl::*;
+    let conn = pool.get().unwrap();
+    tag_article
+        .filter(article_id.eq(given_article_id))
+        .select(tag)
+        .load::<String>(&conn)
+}
+
+/// Slugs of the articles the user currently favorites.
+#[tracing::instrument(skip_all, fields(user_id = given_user_id))]
+pub fn get_favorited_slugs(pool: &DbPool, given_user_id: i32) -> QueryResult<Vec<String>> {
+    use crate::db_schema::articles;
+    use crate::db_schema::user_favorites_article::dsl::*;
+    let conn = pool.get().unwrap();
+    user_favorites_article
+        .inner_join(articles::table)
+        .filter(user_id.eq(given_user_id).and(active.eq(true)))
+        .select(articles::slug)
+        .load::<String>(&conn)
+}
+
+/// Records an uploaded asset unless it would take its uploader over
+/// `quota_bytes`, in which case it returns `None`. The uploader's row is
+/// locked so that concurrent uploads can't both fit in the same room. A new
+/// cover replaces the previous one.
+#[tracing::instrument(skip_all, fields(article_id = new_asset.article_id))]
+pub fn create_asset(
+    pool: &DbPool,
+    new_asset: NewArticleAssetDTO,
+    quota_bytes: i64,
+) -> QueryResult<Option<ArticleAssetEntity>> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        users::table
+            .filter(users::id.eq(new_asset.uploader_id))
+            .select(users::id)
+            .for_update()
+            .first::<i32>(&conn)?;
+        if asset_usage(&conn, new_asset.uploader_id)? + new_asset.size_bytes as i64 > quota_bytes {
+            return Ok(None);
+        }
+        if new_asset.is_cover {
+            diesel::update(
+                article_assets::table
+                    .filter(article_assets::article_id.eq(new_asset.article_id))
+                    .filter(article_assets::is_cover.eq(true)),
+            )
+            .set(article_assets::is_cover.eq(false))
+            .execute(&conn)?;
+        }
+        diesel::insert_into(article_assets::table)
+            .values(&new_asset)
+            .returning(ARTICLE_ASSET_COLUMNS)
+            .get_result::<ArticleAssetEntity>(&conn)
+            .map(Some)
+    })
+}
+
+#[tracing::instrument(skip_all, fields(article_id = given_article_id))]
+pub fn get_assets(pool: &DbPool, given_article_id: i32) -> QueryResult<Vec<ArticleAssetEntity>> {
+    let conn = pool.get().unwrap();
+    article_assets::table
+        .filter(article_assets::article_id.eq(given_article_id))
+        .order(article_assets::id.asc())
+        .select(ARTICLE_ASSET_COLUMNS)
+        .load::<ArticleAssetEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(asset_id = given_asset_id))]
+pub fn get_asset(pool: &DbPool, given_asset_id: i32) -> QueryResult<ArticleAssetEntity> {
+    let conn = pool.get().unwrap();
+    article_assets::table
+        .filter(article_assets::id.eq(given_asset_id))
+        .select(ARTICLE_ASSET_COLUMNS)
+        .first::<ArticleAssetEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(article_id = given_article_id))]
+pub fn get_cover_key(pool: &DbPool, given_article_id: i32) -> QueryResult<Option<String>> {
+    let conn = pool.get().unwrap();
+    article_assets::table
+        .filter(article_assets::article_id.eq(given_article_id))
+        .filter(article_assets::is_cover.eq(true))
+        .select(article_assets::key)
+        .first::<String>(&conn)
+        .optional()
+}
+
+/// Bytes taken by the assets a user uploaded to existing articles.
+#[tracing::instrument(skip_all, fields(user_id = given_user_id))]
+pub fn get_asset_usage(pool: &DbPool, given_user_id: i32) -> QueryResult<i64> {
+    let conn = pool.get().unwrap();
+    asset_usage(&conn, given_user_id)
+}
+
+fn asset_usage(conn: &PgConnection, given_user_id: i32) -> QueryResult<i64> {
+    article_assets::table
+        .filter(article_assets::uploader_id.eq(given_user_id))
+        .filter(article_assets::article_id.is_not_null())
+        .select(diesel::dsl::sum(article_assets::size_bytes))
+        .first::<Option<i64>>(conn)
+        .map(|usage| usage.unwrap_or(0))
+}
+
+/// Detaches an asset from its article, leaving its file to be removed.
+#[tracing::instrument(skip_all, fields(asset_id = given_asset_id))]
+pub fn detach_asset(pool: &DbPool, given_asset_id: i32) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::update(article_assets::table.filter(article_assets::id.eq(given_asset_id)))
+        .set((
+            article_assets::article_id.eq(None::<i32>),
+            article_assets::is_cover.eq(false),
+        ))
+        .execute(&conn)
+        .map(|_| ())
+}
+
+/// Up to `limit` assets whose article is gone, as `(id, key)`.
+#[tracing::instrument(skip_all)]
+pub fn get_detached_assets(pool: &DbPool, limit: i64) -> QueryResult<Vec<(i32, String)>> {
+    let conn = pool.get().unwrap();
+    article_assets::table
+        .filter(article_assets::article_id.is_null())
+        .order(article_assets::id.asc())
+        .limit(limit)
+        .select((article_assets::id, article_assets::key))
+        .load::<(i32, String)>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(asset_id = given_asset_id))]
+pub fn delete_asset(pool: &DbPool, given_asset_id: i32) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::delete(article_assets::table.filter(article_assets::id.eq(given_asset_id)))
+        .execute(&conn)
+        .map(|_| ())
 }
@@ -1,7 +1,15 @@
 use juniper::{graphql_value, FieldError, IntoFieldError};
 
 pub enum ArticleError {
-    NotFound
+    NotFound,
+    /// The uploaded asset was rejected, with why.
+    InvalidAsset(&'static str),
+    /// The upload would take the user over their storage quota.
+    QuotaExceeded,
+    /// The article can't go to the requested status, with why.
+    InvalidStatus(&'static str),
+    /// The article has no revision with that number, or it was pruned.
+    RevisionNotFound,
 }
 
 impl IntoFieldError for ArticleError {
@@ -9,6 +17,18 @@ impl IntoFieldError for ArticleError {
         match self {
             ArticleError::NotFound => FieldError::new("Not found", graphql_value!({
                 "code": "article.not.found"
+            }) ),
+            ArticleError::InvalidAsset(reason) => FieldError::new(reason, graphql_value!({
+                "code": "invalid.asset"
+            }) ),
+            ArticleError::QuotaExceeded => FieldError::new("Storage quota exceeded", graphql_value!({
+                "code": "asset.quota.exceeded"
+            }) ),
+            ArticleError::InvalidStatus(reason) => FieldError::new(reason, graphql_value!({
+                "code": "invalid.article.status"
+            }) ),
+            ArticleError::RevisionNotFound => FieldError::new("Revision not found", graphql_value!({
+                "code": "revision.not.found"
             }) )
         }
     }
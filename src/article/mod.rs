@@ -1,4 +1,10 @@
+#[allow(non_local_definitions)] // diesel 1.4 derives and macros
 pub mod db;
 pub mod model;
 pub mod resolvers;
 pub mod errors;
+pub mod assets;
+pub mod render;
+pub mod text;
+pub mod scheduler;
+pub mod revisions;
@@ -1,11 +1,109 @@
-use super::db::ArticleEntity;
+use super::db::{ArticleAssetEntity, ArticleEntity, ArticleRevisionEntity};
+use super::render::TocEntry;
+use super::revisions::{self, DiffFormat};
+use super::text;
+use crate::errors::internal_server_error;
 use crate::schema::Context;
-use crate::user::model::Profile;
+use crate::user::model::{Profile, Scope};
 use chrono::{Utc, DateTime};
-use juniper::FieldResult;
+use juniper::{FieldResult, GraphQLEnum, GraphQLObject};
 use crate::user::auth;
 use diesel::prelude::*;
 
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[graphql(description = "Where an article is in its publishing lifecycle")]
+pub enum ArticleStatus {
+    #[graphql(description = "Only visible to its author")]
+    Draft,
+    #[graphql(description = "A draft that will be published at `publishAt`")]
+    Scheduled,
+    Published,
+    #[graphql(description = "Taken down by its author, only visible to them")]
+    Archived,
+}
+
+impl ArticleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArticleStatus::Draft => "draft",
+            ArticleStatus::Scheduled => "scheduled",
+            ArticleStatus::Published => "published",
+            ArticleStatus::Archived => "archived",
+        }
+    }
+
+    /// Unknown values fall back to the least visible status.
+    pub fn parse(status: &str) -> ArticleStatus {
+        match status {
+            "scheduled" => ArticleStatus::Scheduled,
+            "published" => ArticleStatus::Published,
+            "archived" => ArticleStatus::Archived,
+            _ => ArticleStatus::Draft,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "An image attached to an article")]
+pub struct ArticleAsset {
+    pub id: i32,
+    pub url: String,
+    pub content_type: String,
+    pub size_bytes: i32,
+    #[graphql(description = "Whether this is the article's cover image")]
+    pub cover: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ArticleAsset {
+    pub fn new(context: &Context, asset: ArticleAssetEntity) -> Self {
+        ArticleAsset {
+            id: asset.id,
+            url: context.blob_store.url(&asset.key),
+            content_type: asset.content_type,
+            size_bytes: asset.size_bytes,
+            cover: asset.is_cover,
+            created_at: asset.created_at,
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context, name = "ArticleRevision")]
+impl ArticleRevisionEntity {
+    /// Counts the saves of the article, from 1.
+    fn number(&self) -> i32 {
+        self.number
+    }
+
+    fn title(&self) -> &str {
+        self.title.as_str()
+    }
+
+    fn description(&self) -> &Option<String> {
+        &self.description
+    }
+
+    fn body(&self) -> &str {
+        self.body.as_str()
+    }
+
+    /// Who saved this revision, unless their account was deleted.
+    fn editor(&self, context: &Context) -> FieldResult<Option<Profile>> {
+        let _span = tracing::info_span!("ArticleRevision.editor").entered();
+        match self.editor_id {
+            Some(editor_id) => {
+                let editor = crate::user::db::get_user_by_id(&context.db_pool, &editor_id)?;
+                Ok(Some(Profile::from(editor)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
 #[juniper::graphql_object(Context = Context, name = "Article")]
 impl ArticleEntity {
 
@@ -21,6 +119,40 @@ impl ArticleEntity {
         self.body.as_str()
     }
 
+    /// The body rendered from Markdown to sanitized HTML.
+    fn body_html(&self) -> String {
+        let _span = tracing::info_span!("Article.bodyHtml").entered();
+        super::render::rendered(self).html.clone()
+    }
+
+    /// The headings of the body, in order.
+    fn toc(&self) -> Vec<TocEntry> {
+        let _span = tracing::info_span!("Article.toc").entered();
+        super::render::rendered(self).toc.clone()
+    }
+
+    fn word_count(&self) -> i32 {
+        self.word_count
+    }
+
+    /// At 200 words per minute, rounded up.
+    fn reading_time_minutes(&self) -> i32 {
+        self.reading_time_minutes
+    }
+
+    /// The beginning of the body as plain text, cut at a word boundary to at
+    /// most `length` characters (200 by default, 500 at most) and followed by
+    /// an ellipsis when the body goes on.
+    fn excerpt(&self, length: Option<i32>) -> String {
+        let length = length.unwrap_or(200).clamp(1, text::EXCERPT_MAX_CHARS as i32) as usize;
+        let excerpt = text::truncate_words(&self.excerpt, length);
+        if excerpt.split_whitespace().count() < self.word_count as usize {
+            format!("{}…", excerpt)
+        } else {
+            excerpt.to_string()
+        }
+    }
+
     fn description(&self) -> &Option<String> {
         &self.description
     }
@@ -33,48 +165,78 @@ impl ArticleEntity {
         self.updated_at
     }
 
-    fn author(&self, context: &Context) -> FieldResult<Profile > {
+    fn status(&self) -> ArticleStatus {
+        ArticleStatus::parse(&self.status)
+    }
+
+    /// When the article was first published.
+    fn published_at(&self) -> Option<DateTime<Utc>> {
+        self.published_at
+    }
+
+    /// When a scheduled article will be published.
+    fn publish_at(&self) -> Option<DateTime<Utc>> {
+        self.publish_at
+    }
+
+    fn author(&self, context: &Context) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("Article.author").entered();
         let pool = &context.db_pool;
         let author = crate::user::db::get_user_by_id(pool, &self.author_id)?;
-        let follower_id = context
-        .token
-        .clone()
-        .map(|token| auth::get_id_from_token(&Some(token)).unwrap());
-        let following;
-        if let Some(found_id) = follower_id {
-            following = crate::user::db::get_follows(pool, &found_id, &author.username)?;
-        } else {
-            following = false;
-        }
-        Ok(Profile {
-            username: author.username,
-            bio: author.bio,
-            image: author.image,
-            following,
-        })
+        Ok(Profile::from(author))
+    }
+
+    /// The URL of the asset uploaded as cover, if any.
+    fn cover_image(&self, context: &Context) -> FieldResult<Option<String>> {
+        let _span = tracing::info_span!("Article.coverImage").entered();
+        let key = super::db::get_cover_key(&context.db_pool, self.id).map_err(internal_server_error)?;
+        Ok(key.map(|key| context.blob_store.url(&key)))
+    }
+
+    fn assets(&self, context: &Context) -> FieldResult<Vec<ArticleAsset>> {
+        let _span = tracing::info_span!("Article.assets").entered();
+        let assets = super::db::get_assets(&context.db_pool, self.id).map_err(internal_server_error)?;
+        Ok(assets
+            .into_iter()
+            .map(|asset| ArticleAsset::new(context, asset))
+            .collect())
+    }
+
+    /// The kept revisions, the latest first. Only visible to the author and
+    /// moderators.
+    fn revisions(&self, context: &Context) -> FieldResult<Vec<ArticleRevisionEntity>> {
+        let _span = tracing::info_span!("Article.revisions").entered();
+        revisions::check_access(context, self)?;
+        super::db::get_revisions(&context.db_pool, self.id).map_err(internal_server_error)
+    }
+
+    /// Only visible to the author and moderators.
+    fn revision(&self, context: &Context, number: i32) -> FieldResult<Option<ArticleRevisionEntity>> {
+        let _span = tracing::info_span!("Article.revision", number).entered();
+        revisions::check_access(context, self)?;
+        super::db::get_revision(&context.db_pool, self.id, number).map_err(internal_server_error)
+    }
+
+    /// The changes to the body from revision `from` to revision `to`, as a
+    /// unified diff by default. Only visible to the author and moderators.
+    fn diff(&self, context: &Context, from: i32, to: i32, format: Option<DiffFormat>) -> FieldResult<String> {
+        let _span = tracing::info_span!("Article.diff", from, to).entered();
+        revisions::check_access(context, self)?;
+        let from = revisions::find(context, self.id, from)?;
+        let to = revisions::find(context, self.id, to)?;
+        Ok(revisions::diff(&from, &to, format.unwrap_or(DiffFormat::Unified)))
     }
 
     fn favorited(&self, context: &Context) -> FieldResult<bool>{
+        let _span = tracing::info_span!("Article.favorited").entered();
 
         let pool = &context.db_pool;
-        let follower_id = context
-        .token
-        .clone()
-        .map(|token| auth::get_id_from_token(&Some(token)).unwrap());
+        let follower_id = auth::get_id_from_token(pool, &context.token, Scope::ArticlesRead).ok();
         if let Some(found_id) = follower_id {
             let result = super::db::get_user_favorites_article(pool, found_id, self.id);
             match result {
                 Ok(favorited) => Ok(favorited),
-                Err(e) => {
-                    eprintln!("{}", e);
-                    use juniper::{graphql_value, FieldError};
-                    Err(FieldError::new(
-                        "Internal Server Error",
-                        graphql_value!({
-                            "code": "internal.server.error"
-                        }),
-                    ))
-                }
+                Err(e) => Err(internal_server_error(e)),
             }
         } else {
             Ok(false)
@@ -82,6 +244,7 @@ impl ArticleEntity {
     }
 
     fn favorites_count(&self, context: &Context) -> FieldResult<i32>{
+        let _span = tracing::info_span!("Article.favoritesCount").entered();
         let pool = &context.db_pool;
         let conn = pool.get().unwrap();
         use crate::db_schema::user_favorites_article::dsl::*;
@@ -91,36 +254,19 @@ impl ArticleEntity {
         ).select(count_star()).first::<i64>(&conn);
         match result {
             Ok(favorites_count) => Ok(favorites_count as i32),
-            Err(e) => {
-                eprintln!("{}", e);
-                use juniper::{graphql_value, FieldError};
-                Err(FieldError::new(
-                    "Internal Server Error",
-                    graphql_value!({
-                        "code": "internal.server.error"
-                    }),
-                ))
-            }
+            Err(e) => Err(internal_server_error(e)),
         }
     }
 
     fn tag_list(&self, context: &Context) -> FieldResult<Vec<String>> {
+        let _span = tracing::info_span!("Article.tagList").entered();
         use crate::db_schema::tag_article::dsl::*;
         let pool = &context.db_pool;
         let conn = pool.get().unwrap();
         let result = tag_article.filter(article_id.eq(self.id)).select(tag).load::<String>(&conn);
         match result {
             Ok(tag_list) => Ok(tag_list),
-            Err(e) => {
-                eprintln!("{}", e);
-                use juniper::{graphql_value, FieldError};
-                Err(FieldError::new(
-                    "Internal Server Error",
-                    graphql_value!({
-                        "code": "internal.server.error"
-                    }),
-                ))
-            }
+            Err(e) => Err(internal_server_error(e)),
         }
     }
 }
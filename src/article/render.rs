@@ -0,0 +1,231 @@
+//! HTML rendering of article bodies: CommonMark with the GFM tables,
+//! strikethrough and task list extensions, headings given anchors for the
+//! table of contents, and fenced code marked with a `language-*` class for
+//! client-side highlighters (highlight.js, Prism...). Authors may write raw
+//! HTML, so the output is sanitized against an allowlist afterwards.
+//!
+//! Rendering is cached per article and replaced whenever `updated_at`
+//! changes. Like the session cache, the cache is per process.
+
+use super::db::ArticleEntity;
+use chrono::{DateTime, Utc};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use slugify::slugify;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// Prefix of the anchors given to headings.
+const ANCHOR_PREFIX: &str = "heading-";
+
+/// Prefix of the ids written by authors in raw HTML, so that they can't clash
+/// with the anchors nor with the ids of the page showing the article.
+const AUTHOR_ID_PREFIX: &str = "user-content-";
+
+/// The cache is emptied when it gets this big.
+const CACHE_CAPACITY: usize = 1_000;
+
+#[derive(Clone, juniper::GraphQLObject)]
+#[graphql(description = "A heading of an article, to build a table of contents")]
+pub struct TocEntry {
+    #[graphql(description = "From 1 to 6, as in `<h1>` to `<h6>`")]
+    pub level: i32,
+    pub text: String,
+    #[graphql(description = "The id of the heading in `bodyHtml`, to link to it with `#anchor`")]
+    pub anchor: String,
+}
+
+pub struct Rendered {
+    pub html: String,
+    pub toc: Vec<TocEntry>,
+}
+
+/// The sanitizer for one rendering. Heading ids are written with `marker`
+/// in front, which authors can't guess: ids carrying it become anchors, any
+/// other id is moved under `AUTHOR_ID_PREFIX`.
+fn sanitizer(marker: String) -> ammonia::Builder<'static> {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("code", ["class"])
+        .add_tag_attributes("th", ["style"])
+        .add_tag_attributes("td", ["style"])
+        .add_generic_attributes(["id"])
+        .attribute_filter(move |element, attribute, value| match (element, attribute) {
+            (_, "id") => Some(match value.strip_prefix(marker.as_str()) {
+                Some(anchor) => Cow::Owned(anchor.to_string()),
+                None => Cow::Owned(format!("{}{}", AUTHOR_ID_PREFIX, value)),
+            }),
+            ("code", "class") => is_language_class(value).then_some(Cow::Borrowed(value)),
+            ("input", "type") => (value == "checkbox").then_some(Cow::Borrowed(value)),
+            ("th" | "td", "style") => {
+                matches!(value, "text-align: left" | "text-align: center" | "text-align: right")
+                    .then_some(Cow::Borrowed(value))
+            }
+            _ => Some(Cow::Borrowed(value)),
+        });
+    builder
+}
+
+/// Renderings by article id, with the `updated_at` they were made for.
+type Cache = Mutex<HashMap<i32, (DateTime<Utc>, Arc<Rendered>)>>;
+
+static CACHE: LazyLock<Cache> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn is_language_class(value: &str) -> bool {
+    value.strip_prefix("language-").is_some_and(|language| {
+        !language.is_empty()
+            && language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+' | '#'))
+    })
+}
+
+/// The rendering of an article, from the cache if it was rendered since its
+/// last update.
+pub fn rendered(article: &ArticleEntity) -> Arc<Rendered> {
+    if let Some((updated_at, rendered)) = CACHE.lock().unwrap().get(&article.id) {
+        if *updated_at == article.updated_at {
+            return rendered.clone();
+        }
+    }
+    let rendered = Arc::new(render(&article.body));
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(article.id, (article.updated_at, rendered.clone()));
+    rendered
+}
+
+/// The Markdown extensions enabled for article bodies.
+pub fn markdown_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options
+}
+
+pub fn render(markdown: &str) -> Rendered {
+    let mut events: Vec<Event> = Parser::new_ext(markdown, markdown_options()).collect();
+
+    let marker = format!("{}-", uuid::Uuid::new_v4().simple());
+    let mut toc = Vec::new();
+    let mut anchors = HashSet::new();
+    let mut heading: Option<(usize, String)> = None;
+    for index in 0..events.len() {
+        match &events[index] {
+            Event::Start(Tag::Heading { .. }) => heading = Some((index, String::new())),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading_text)) = heading.as_mut() {
+                    heading_text.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let level = *level as i32;
+                if let Some((start, text)) = heading.take() {
+                    let anchor = unique_anchor(&mut anchors, &text);
+                    if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+                        *id = Some(format!("{}{}", marker, anchor).into());
+                    }
+                    toc.push(TocEntry {
+                        level,
+                        text: text.trim().to_string(),
+                        anchor,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Only keep the first word of info strings such as "rust,ignore".
+    for event in events.iter_mut() {
+        if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) = event {
+            let language = info
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            *info = language.into();
+        }
+    }
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    Rendered {
+        html: sanitizer(marker).clean(&html).to_string(),
+        toc,
+    }
+}
+
+/// A prefixed slug of a heading, numbered when an earlier heading has the
+/// same one.
+fn unique_anchor(anchors: &mut HashSet<String>, text: &str) -> String {
+    let mut slug = slugify!(text);
+    if slug.is_empty() {
+        slug = "section".to_string();
+    }
+    let base = format!("{}{}", ANCHOR_PREFIX, slug);
+    let mut anchor = base.clone();
+    let mut n = 1;
+    while !anchors.insert(anchor.clone()) {
+        anchor = format!("{}-{}", base, n);
+        n += 1;
+    }
+    anchor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings_get_unique_anchors() {
+        let rendered = render("# Intro\n\n## Intro\n\n### `code` *here*\n\n# !!!");
+        let anchors: Vec<_> = rendered.toc.iter().map(|entry| entry.anchor.as_str()).collect();
+        assert_eq!(anchors, ["heading-intro", "heading-intro-1", "heading-code-here", "heading-section"]);
+        assert_eq!(rendered.toc[2].level, 3);
+        assert_eq!(rendered.toc[2].text, "code here");
+        assert!(rendered.html.contains(r#"<h1 id="heading-intro">Intro</h1>"#));
+        assert!(rendered.html.contains(r#"<h2 id="heading-intro-1">Intro</h2>"#));
+    }
+
+    #[test]
+    fn author_ids_cannot_take_anchors() {
+        let rendered = render("<p id=\"heading-intro\">raw</p>\n\n# Intro\n\n<h2 id=\"x\">raw</h2>");
+        assert!(rendered.html.contains(r#"<p id="user-content-heading-intro">raw</p>"#));
+        assert!(rendered.html.contains(r#"<h1 id="heading-intro">Intro</h1>"#));
+        assert!(rendered.html.contains(r#"<h2 id="user-content-x">raw</h2>"#));
+    }
+
+    #[test]
+    fn sanitizes_raw_html() {
+        let html = render("<script>alert(1)</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"x()\">link</a>").html;
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onclick"));
+        assert!(html.contains(">link</a>"));
+    }
+
+    #[test]
+    fn keeps_language_classes_only() {
+        let html = render("```rust,ignore\nfn main() {}\n```").html;
+        assert!(html.contains(r#"<code class="language-rust">"#));
+        let html = render("<code class=\"language-c++\">a</code> <code class=\"evil\">b</code>").html;
+        assert!(html.contains(r#"<code class="language-c++">a</code>"#));
+        assert!(html.contains("<code>b</code>"));
+    }
+
+    #[test]
+    fn keeps_task_lists_and_table_alignment() {
+        let html = render("- [x] done\n- [ ] todo\n\n| a | b |\n|:-:|---|\n| 1 | 2 |").html;
+        assert!(html.contains(r#"<input disabled="" type="checkbox" checked="">"#));
+        assert!(html.contains(r#"<th style="text-align: center">a</th>"#));
+        let html = render("<input type=\"text\"> <td style=\"color: red\">x</td>").html;
+        assert!(!html.contains("type=\"text\""));
+        assert!(!html.contains("color"));
+    }
+}
@@ -1,8 +1,17 @@
 use juniper::{FieldResult, GraphQLInputObject, GraphQLObject, IntoFieldError};
 
+use super::assets;
 use super::db::ArticleEntity;
+use super::errors::ArticleError;
+use super::model::{ArticleAsset, ArticleStatus};
+use crate::errors::internal_server_error;
 use crate::schema::Context;
+use crate::upload::Upload;
 use crate::user::auth;
+use crate::user::errors::UserError;
+use crate::user::model::{Role, Scope};
+use chrono::{DateTime, Utc};
+use tracing::Instrument;
 
 #[derive(GraphQLInputObject)]
 #[graphql(description = "Payload to create an article")]
@@ -13,6 +22,8 @@ pub struct NewArticle {
     pub tag_list: Option<Vec<String>>,
 }
 
+#[derive(GraphQLInputObject)]
+#[graphql(description = "Payload to update an article, omitted fields are left unchanged")]
 pub struct UpdateArticle {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -20,55 +31,286 @@ pub struct UpdateArticle {
     pub tag_list: Option<Vec<String>>,
 }
 
+/// Whether only users with a verified email may publish, from `REQUIRE_VERIFIED_EMAIL`.
+fn require_verified_email() -> bool {
+    matches!(
+        std::env::var("REQUIRE_VERIFIED_EMAIL").as_deref(),
+        Ok("true") | Ok("1")
+    )
+}
+
+/// Fails if the author may not publish yet, see `require_verified_email`.
+fn check_can_publish(context: &Context, author_id: i32) -> FieldResult<()> {
+    if require_verified_email() {
+        let author = crate::user::db::get_user_by_id(&context.db_pool, &author_id)
+            .map_err(internal_server_error)?;
+        if author.email_verified_at.is_none() {
+            return Err(UserError::EmailNotVerified.into_field_error());
+        }
+    }
+    Ok(())
+}
+
+fn find_by_slug(context: &Context, slug: String) -> FieldResult<ArticleEntity> {
+    match super::db::get_by_slug(&context.db_pool, slug) {
+        Ok(article) => Ok(article),
+        Err(diesel::result::Error::NotFound) => Err(ArticleError::NotFound.into_field_error()),
+        Err(e) => Err(internal_server_error(e)),
+    }
+}
+
+/// The viewer's own article with this slug; only authors choose when their
+/// articles get published.
+fn find_own_article(context: &Context, slug: String) -> FieldResult<ArticleEntity> {
+    let viewer = auth::get_viewer_from_token(&context.db_pool, &context.token, Scope::ArticlesWrite)?;
+    let article = find_by_slug(context, slug)?;
+    if article.author_id != viewer.id {
+        return Err(UserError::Forbidden.into_field_error());
+    }
+    Ok(article)
+}
+
 pub struct ArticleMutation;
 
 #[juniper::graphql_object(Context = Context)]
 impl ArticleMutation {
     fn create_article(context: &Context, new_article: NewArticle) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.createArticle").entered();
         use super::db::create;
         let pool = &context.db_pool;
-        let id = auth::get_id_from_token(&context.token);
+        let id = auth::get_id_from_token(&context.db_pool, &context.token, Scope::ArticlesWrite);
         if let Err(e) = id {
             return Err(e);
         };
         let author_id = id.unwrap();
-        let article = create(pool, new_article, author_id)?;
+        check_can_publish(context, author_id)?;
+        let article = create(pool, new_article, author_id, ArticleStatus::Published)?;
         Ok(article)
     }
 
-    //fn update_article(
-    //context: &Context,
-    //update_article: UpdateArticle,
-    //) -> FieldResult<ArticleEntity> {
-    //use super::db::create;
-    //let pool = &context.db_pool;
-    //let id = auth::get_id_from_token(&context.token);
-    //if let Err(e) = id {
-    //return Err(e);
-    //};
-    //let author_id = id.unwrap();
-    //let article = create(pool, update_article, author_id)?;
-    //Ok(article)
-    //}
-
-    fn delete_article(context: &Context, article_slug: String) -> FieldResult<String> {
-        use super::db::delete;
+    /// Creates an article as a draft, which `updateArticle` edits until it
+    /// is published with `publishArticle` or `schedulePublish`.
+    fn save_draft(context: &Context, new_article: NewArticle) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.saveDraft").entered();
         let pool = &context.db_pool;
-        let id = auth::get_id_from_token(&context.token);
-        if let Err(e) = id {
-            return Err(e);
-        };
-        let author_id = id.unwrap();
+        let author_id = auth::get_id_from_token(pool, &context.token, Scope::ArticlesWrite)?;
+        super::db::create(pool, new_article, author_id, ArticleStatus::Draft).map_err(internal_server_error)
+    }
+
+    /// Publishes an article now, whatever its status.
+    fn publish_article(context: &Context, article_slug: String) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.publishArticle", slug = %article_slug).entered();
+        let article = find_own_article(context, article_slug)?;
+        if article.status == ArticleStatus::Published.as_str() {
+            return Ok(article);
+        }
+        check_can_publish(context, article.author_id)?;
+        let article = super::db::publish(&context.db_pool, article.id).map_err(internal_server_error)?;
+        tracing::info!(article_id = article.id, "article published");
+        Ok(article)
+    }
+
+    /// Publishes an unpublished article at `publishAt`, replacing any earlier
+    /// schedule.
+    fn schedule_publish(
+        context: &Context,
+        article_slug: String,
+        publish_at: DateTime<Utc>,
+    ) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.schedulePublish", slug = %article_slug).entered();
+        let article = find_own_article(context, article_slug)?;
+        if article.status == ArticleStatus::Published.as_str() {
+            return Err(ArticleError::InvalidStatus("Article is already published").into_field_error());
+        }
+        if publish_at <= Utc::now() {
+            return Err(ArticleError::InvalidStatus("publishAt must be in the future").into_field_error());
+        }
+        check_can_publish(context, article.author_id)?;
+        super::db::schedule(&context.db_pool, article.id, publish_at).map_err(internal_server_error)
+    }
+
+    /// Takes an article out of every listing; its author can still read it
+    /// and publish it again.
+    fn archive_article(context: &Context, article_slug: String) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.archiveArticle", slug = %article_slug).entered();
+        let article = find_by_slug(context, article_slug)?;
+        let editor_id = editor_id(context, &article)?;
+        if article.status == ArticleStatus::Archived.as_str() {
+            return Ok(article);
+        }
+        if editor_id != article.author_id {
+            tracing::info!(article_id = article.id, moderator_id = editor_id, "article archived by moderator");
+        }
+        super::db::archive(&context.db_pool, article.id).map_err(internal_server_error)
+    }
+
+    fn update_article(
+        context: &Context,
+        article_slug: String,
+        update_article: UpdateArticle,
+    ) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.updateArticle", slug = %article_slug).entered();
+        let pool = &context.db_pool;
+        let viewer = auth::get_viewer_from_token(&context.db_pool, &context.token, Scope::ArticlesWrite)?;
         use super::db::get_by_slug;
-        let article = get_by_slug(pool, article_slug)?;
-        if article.author_id != author_id {
-            return Err(crate::user::errors::UserError::Unauthorized.into_field_error());
+        let article = match get_by_slug(pool, article_slug) {
+            Ok(article) => article,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(super::errors::ArticleError::NotFound.into_field_error())
+            }
+            Err(e) => return Err(internal_server_error(e)),
+        };
+        if article.author_id != viewer.id {
+            auth::require_role(pool, &context.token, Role::Moderator)?;
+            tracing::info!(article_id = article.id, moderator_id = viewer.id, "article updated by moderator");
         }
-        delete(pool, article.id)?;
-        Ok(article.slug)
+        use super::db::update;
+        let article = update(pool, article.id, update_article, viewer.id).map_err(internal_server_error)?;
+        Ok(article)
+    }
+
+    /// Puts back the title, description and body of revision `number`,
+    /// recorded as a new revision.
+    fn restore_revision(context: &Context, slug: String, number: i32) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleMutation.restoreRevision", slug = %slug, number).entered();
+        let article = find_by_slug(context, slug)?;
+        let editor_id = editor_id(context, &article)?;
+        let pool = &context.db_pool;
+        match super::db::restore_revision(pool, article.id, number, editor_id) {
+            Ok(article) => {
+                tracing::info!(article_id = article.id, number, editor_id, "article revision restored");
+                Ok(article)
+            }
+            Err(diesel::result::Error::NotFound) => Err(ArticleError::RevisionNotFound.into_field_error()),
+            Err(e) => Err(internal_server_error(e)),
+        }
+    }
+
+    async fn delete_article(context: &Context, article_slug: String) -> FieldResult<String> {
+        let span = tracing::info_span!("ArticleMutation.deleteArticle", slug = %article_slug);
+        async move {
+            use super::db::delete;
+            let pool = &context.db_pool;
+            let id = auth::get_id_from_token(&context.db_pool, &context.token, Scope::ArticlesWrite);
+            if let Err(e) = id {
+                return Err(e);
+            };
+            let author_id = id.unwrap();
+            use super::db::get_by_slug;
+            let article = get_by_slug(pool, article_slug)?;
+            if article.author_id != author_id {
+                auth::require_role(pool, &context.token, Role::Moderator)?;
+                tracing::info!(article_id = article.id, moderator_id = author_id, "article deleted by moderator");
+            }
+            let detached = delete(pool, article.id)?;
+            assets::remove(pool, context.blob_store.as_ref(), detached).await;
+            Ok(article.slug)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Attaches an image to an article, as its cover when `cover` is true.
+    /// Counts towards the uploader's storage quota.
+    async fn upload_article_asset(
+        context: &Context,
+        slug: String,
+        file: Upload,
+        cover: Option<bool>,
+    ) -> FieldResult<ArticleAsset> {
+        let span = tracing::info_span!("ArticleMutation.uploadArticleAsset", slug = %slug);
+        upload_article_asset(context, slug, file, cover.unwrap_or(false))
+            .instrument(span)
+            .await
+    }
+
+    async fn delete_article_asset(context: &Context, id: i32) -> FieldResult<bool> {
+        let span = tracing::info_span!("ArticleMutation.deleteArticleAsset", asset_id = id);
+        async move {
+            let pool = &context.db_pool;
+            let asset = match super::db::get_asset(pool, id) {
+                Ok(asset) => asset,
+                Err(diesel::result::Error::NotFound) => {
+                    return Err(super::errors::ArticleError::NotFound.into_field_error())
+                }
+                Err(e) => return Err(internal_server_error(e)),
+            };
+            let article_id = match asset.article_id {
+                Some(article_id) => article_id,
+                None => return Err(super::errors::ArticleError::NotFound.into_field_error()),
+            };
+            let article = super::db::get_by_id(pool, article_id).map_err(internal_server_error)?;
+            editor_id(context, &article)?;
+            super::db::detach_asset(pool, asset.id).map_err(internal_server_error)?;
+            assets::remove(pool, context.blob_store.as_ref(), vec![(asset.id, asset.key)]).await;
+            Ok(true)
+        }
+        .instrument(span)
+        .await
     }
 }
 
+/// The viewer if they may edit the article: its author or a moderator.
+fn editor_id(context: &Context, article: &ArticleEntity) -> FieldResult<i32> {
+    let pool = &context.db_pool;
+    let viewer = auth::get_viewer_from_token(pool, &context.token, Scope::ArticlesWrite)?;
+    if article.author_id != viewer.id {
+        auth::require_role(pool, &context.token, Role::Moderator)?;
+    }
+    Ok(viewer.id)
+}
+
+async fn upload_article_asset(
+    context: &Context,
+    slug: String,
+    file: Upload,
+    cover: bool,
+) -> FieldResult<ArticleAsset> {
+    use super::db::{create_asset, get_by_slug, NewArticleAssetDTO};
+    let pool = &context.db_pool;
+    let article = match get_by_slug(pool, slug) {
+        Ok(article) => article,
+        Err(diesel::result::Error::NotFound) => return Err(ArticleError::NotFound.into_field_error()),
+        Err(e) => return Err(internal_server_error(e)),
+    };
+    let uploader_id = editor_id(context, &article)?;
+    let file = context
+        .uploads
+        .take(&file)
+        .ok_or_else(|| ArticleError::InvalidAsset("Asset file is missing").into_field_error())?;
+    let format = assets::check(&file).map_err(IntoFieldError::into_field_error)?;
+    let size_bytes = file.bytes.len() as i32;
+    assets::check_quota(pool, uploader_id, size_bytes as i64)?;
+
+    let key = assets::key(article.id, format);
+    let content_type = format.to_mime_type();
+    context
+        .blob_store
+        .put(&key, content_type, file.bytes)
+        .await
+        .map_err(internal_server_error)?;
+    let new_asset = NewArticleAssetDTO {
+        article_id: article.id,
+        uploader_id,
+        key: key.clone(),
+        content_type: content_type.to_string(),
+        size_bytes,
+        is_cover: cover,
+    };
+    let error = match create_asset(pool, new_asset, assets::quota_bytes()) {
+        Ok(Some(asset)) => {
+            tracing::info!(article_id = article.id, asset_id = asset.id, size_bytes, "article asset uploaded");
+            return Ok(ArticleAsset::new(context, asset));
+        }
+        Ok(None) => ArticleError::QuotaExceeded.into_field_error(),
+        Err(e) => internal_server_error(e),
+    };
+    if let Err(e) = context.blob_store.delete(&key).await {
+        tracing::warn!(error = %e, key = %key, "failed to delete orphaned asset file");
+    }
+    Err(error)
+}
+
 #[derive(GraphQLInputObject)]
 pub struct ArticlesOptions {
     pub tag: Option<String>,
@@ -91,53 +333,76 @@ pub struct FeedOptions {
     pub offset: Option<i32>,
 }
 
+/// Lets the authors of a page load their follow data in one batch.
+fn prime_authors(context: &Context, page: &ArticlesPage) {
+    context
+        .follow_loader
+        .prime(page.articles.iter().map(|article| article.author_id));
+}
+
+/// The signed-in viewer, if any, whose blocks and mutes filter what they read.
+fn viewer_id(context: &Context) -> Option<i32> {
+    auth::get_id_from_token(&context.db_pool, &context.token, Scope::ArticlesRead).ok()
+}
+
 pub struct ArticleQuery;
 
 #[juniper::graphql_object(Context = Context)]
 impl ArticleQuery {
     fn get_article(context: &Context, slug: String) -> FieldResult<ArticleEntity> {
+        let _span = tracing::info_span!("ArticleQuery.getArticle", slug = %slug).entered();
         let pool = &context.db_pool;
         use super::db::get_by_slug;
         let article_result = get_by_slug(pool, slug);
-        match article_result {
+        let article = match article_result {
             Err(diesel::result::Error::NotFound) => {
-                Err(super::errors::ArticleError::NotFound.into_field_error())
+                return Err(super::errors::ArticleError::NotFound.into_field_error())
             }
-            Ok(article) => Ok(article),
-            Err(e) => {
-                eprintln!("{}", e);
-                use juniper::{graphql_value, FieldError};
-                Err(FieldError::new(
-                    "Internal Server Error",
-                    graphql_value!({
-                        "code": "internal.server.error"
-                    }),
-                ))
+            Ok(article) => article,
+            Err(e) => return Err(internal_server_error(e)),
+        };
+        let viewer_id = viewer_id(context);
+        if article.status != ArticleStatus::Published.as_str() && viewer_id != Some(article.author_id) {
+            return Err(super::errors::ArticleError::NotFound.into_field_error());
+        }
+        if let Some(viewer_id) = viewer_id {
+            use crate::user::db::has_blocked;
+            if has_blocked(pool, &article.author_id, &viewer_id).map_err(internal_server_error)? {
+                return Err(super::errors::ArticleError::NotFound.into_field_error());
             }
         }
+        Ok(article)
     }
 
     fn get_articles(context: &Context, options: ArticlesOptions) -> FieldResult<ArticlesPage> {
+        let _span = tracing::info_span!("ArticleQuery.getArticles").entered();
         let pool = &context.db_pool;
         use super::db::get_articles;
-        let articles_result = get_articles(pool, options);
+        let articles_result = get_articles(pool, options, viewer_id(context));
         match articles_result {
-            Ok(articles) => Ok(articles),
-            Err(e) => {
-                eprintln!("{}", e);
-                use juniper::{graphql_value, FieldError};
-                Err(FieldError::new(
-                    "Internal Server Error",
-                    graphql_value!({
-                        "code": "internal.server.error"
-                    }),
-                ))
+            Ok(page) => {
+                prime_authors(context, &page);
+                Ok(page)
             }
+            Err(e) => Err(internal_server_error(e)),
         }
     }
 
+    /// The viewer's drafts and scheduled articles.
+    fn my_drafts(context: &Context, options: Option<FeedOptions>) -> FieldResult<ArticlesPage> {
+        let _span = tracing::info_span!("ArticleQuery.myDrafts").entered();
+        let pool = &context.db_pool;
+        let author_id = auth::get_id_from_token(pool, &context.token, Scope::ArticlesRead)?;
+        let options = options.unwrap_or(FeedOptions {
+            limit: None,
+            offset: None,
+        });
+        super::db::get_drafts(pool, author_id, options).map_err(internal_server_error)
+    }
+
     fn feed(context: &Context, options: Option<FeedOptions>) -> FieldResult<ArticlesPage> {
-        let id = auth::get_id_from_token(&context.token);
+        let _span = tracing::info_span!("ArticleQuery.feed").entered();
+        let id = auth::get_id_from_token(&context.db_pool, &context.token, Scope::ArticlesRead);
         if let Err(e) = id {
             return Err(e);
         };
@@ -153,17 +418,11 @@ impl ArticleQuery {
         use super::db::get_feed;
         let articles_result = get_feed(pool, user_id, feed_options);
         match articles_result {
-            Ok(articles) => Ok(articles),
-            Err(e) => {
-                eprintln!("{}", e);
-                use juniper::{graphql_value, FieldError};
-                Err(FieldError::new(
-                    "Internal Server Error",
-                    graphql_value!({
-                        "code": "internal.server.error"
-                    }),
-                ))
+            Ok(page) => {
+                prime_authors(context, &page);
+                Ok(page)
             }
+            Err(e) => Err(internal_server_error(e)),
         }
     }
 }
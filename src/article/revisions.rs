@@ -0,0 +1,92 @@
+//! Article revisions: every create, update and restore records the text it
+//! saved, so authors can compare versions and go back to an earlier one.
+//! Only the last `ARTICLE_REVISIONS_KEEP` revisions of an article are kept
+//! (50 by default, `0` keeps them all).
+
+use super::db::{self, ArticleEntity, ArticleRevisionEntity};
+use super::errors::ArticleError;
+use crate::errors::internal_server_error;
+use crate::schema::Context;
+use crate::user::auth;
+use crate::user::model::{Role, Scope};
+use juniper::{FieldResult, GraphQLEnum, IntoFieldError};
+use similar::{ChangeTag, TextDiff};
+
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[graphql(description = "How the differences between two revisions are shown")]
+pub enum DiffFormat {
+    #[graphql(description = "A unified diff of the lines, as produced by `diff -u`")]
+    Unified,
+    #[graphql(description = "The text with removed words as `[-words-]` and added ones as `{+words+}`")]
+    Word,
+}
+
+/// How many revisions to keep per article, or `None` to keep them all.
+pub fn keep() -> Option<i32> {
+    let keep = std::env::var("ARTICLE_REVISIONS_KEEP")
+        .ok()
+        .and_then(|keep| keep.parse().ok())
+        .unwrap_or(50);
+    (keep > 0).then_some(keep)
+}
+
+/// Fails unless the viewer may read the history of the article: earlier
+/// revisions can hold text the author took out, so only they and
+/// moderators can.
+pub fn check_access(context: &Context, article: &ArticleEntity) -> FieldResult<()> {
+    let pool = &context.db_pool;
+    let viewer = auth::get_viewer_from_token(pool, &context.token, Scope::ArticlesRead)?;
+    if article.author_id != viewer.id {
+        auth::require_role(pool, &context.token, Role::Moderator)?;
+    }
+    Ok(())
+}
+
+pub fn find(context: &Context, article_id: i32, number: i32) -> FieldResult<ArticleRevisionEntity> {
+    db::get_revision(&context.db_pool, article_id, number)
+        .map_err(internal_server_error)?
+        .ok_or_else(|| ArticleError::RevisionNotFound.into_field_error())
+}
+
+/// The differences between the bodies of two revisions.
+pub fn diff(from: &ArticleRevisionEntity, to: &ArticleRevisionEntity, format: DiffFormat) -> String {
+    match format {
+        DiffFormat::Unified => TextDiff::from_lines(&from.body, &to.body)
+            .unified_diff()
+            .header(
+                &format!("revision {}", from.number),
+                &format!("revision {}", to.number),
+            )
+            .to_string(),
+        DiffFormat::Word => word_diff(&from.body, &to.body),
+    }
+}
+
+fn word_diff(from: &str, to: &str) -> String {
+    let text_diff = TextDiff::from_words(from, to);
+    let mut output = String::new();
+    let mut open: Option<ChangeTag> = None;
+    for change in text_diff.iter_all_changes() {
+        let tag = change.tag();
+        if open != Some(tag) {
+            close(&mut output, open);
+            match tag {
+                ChangeTag::Delete => output.push_str("[-"),
+                ChangeTag::Insert => output.push_str("{+"),
+                ChangeTag::Equal => {}
+            }
+            open = Some(tag);
+        }
+        output.push_str(change.value());
+    }
+    close(&mut output, open);
+    output
+}
+
+fn close(output: &mut String, tag: Option<ChangeTag>) {
+    match tag {
+        Some(ChangeTag::Delete) => output.push_str("-]"),
+        Some(ChangeTag::Insert) => output.push_str("+}"),
+        _ => {}
+    }
+}
@@ -0,0 +1,32 @@
+//! Publishes scheduled articles when they come due. Every replica runs the
+//! scheduler; publishing is a single conditional `UPDATE`, so an article is
+//! published once whichever replica gets to it first.
+
+use super::db;
+use crate::db::DbPool;
+use actix_web::web;
+use std::time::Duration;
+
+/// Checks for due articles every `ARTICLE_SCHEDULER_SECONDS` (30 by default,
+/// at least 1).
+pub async fn run(pool: DbPool) {
+    let seconds = std::env::var("ARTICLE_SCHEDULER_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(30)
+        .max(1);
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(seconds));
+    loop {
+        interval.tick().await;
+        let pool = pool.clone();
+        match web::block(move || db::publish_due(&pool)).await {
+            Ok(Ok(published)) => {
+                for article_id in published {
+                    tracing::info!(article_id, "scheduled article published");
+                }
+            }
+            Ok(Err(e)) => tracing::error!(error = %e, "failed to publish scheduled articles"),
+            Err(e) => tracing::error!(error = %e, "failed to publish scheduled articles"),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Figures computed from the text of an article body, with its Markdown
+//! markup and raw HTML left out. They are stored with the article when its
+//! body is written, so listings don't need the body to show them.
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+/// Reading speed used for `reading_time_minutes`.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// The longest stored excerpt, in characters; `Article.excerpt` can only
+/// shorten it.
+pub const EXCERPT_MAX_CHARS: usize = 500;
+
+pub struct TextStats {
+    pub word_count: i32,
+    pub reading_time_minutes: i32,
+    pub excerpt: String,
+}
+
+pub fn stats(markdown: &str) -> TextStats {
+    let text = plain_text(markdown);
+    let word_count = text.split_whitespace().count();
+    TextStats {
+        word_count: word_count as i32,
+        reading_time_minutes: word_count.div_ceil(WORDS_PER_MINUTE) as i32,
+        excerpt: truncate_words(&text, EXCERPT_MAX_CHARS).to_string(),
+    }
+}
+
+/// The words of a Markdown document separated by single spaces. Image
+/// descriptions are left out along with the markup.
+pub fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut images = 0;
+    for event in Parser::new_ext(markdown, super::render::markdown_options()) {
+        match event {
+            Event::Start(Tag::Image { .. }) => images += 1,
+            Event::End(TagEnd::Image) => images -= 1,
+            Event::Text(words) | Event::Code(words) if images == 0 => {
+                text.push_str(&words);
+            }
+            // Breaks and the ends of blocks separate words, inline tags don't.
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::CodeBlock
+                | TagEnd::TableCell
+                | TagEnd::BlockQuote(_),
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// At most `max_chars` characters of `text`, cut at a space when it is longer.
+pub fn truncate_words(text: &str, max_chars: usize) -> &str {
+    let Some((end, _)) = text.char_indices().nth(max_chars) else {
+        return text;
+    };
+    let cut = &text[..end];
+    if text[end..].starts_with(' ') {
+        return cut;
+    }
+    match cut.rfind(' ') {
+        Some(space) => &cut[..space],
+        None => cut,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_drops_markup_and_images() {
+        let markdown = "# A *title*\n\nSome `code` and a [link](https://example.com).\n\n\
+                        ![an image](cat.png)\n\n- one\n- two\n\n<b>raw</b> html";
+        assert_eq!(plain_text(markdown), "A title Some code and a link. one two raw html");
+    }
+
+    #[test]
+    fn plain_text_separates_blocks_and_table_cells() {
+        assert_eq!(plain_text("first\nline\n\n> quoted"), "first line quoted");
+        assert_eq!(plain_text("| a | b |\n|---|---|\n| c | d |"), "a b c d");
+    }
+
+    #[test]
+    fn truncate_words_keeps_short_text() {
+        assert_eq!(truncate_words("short text", 10), "short text");
+        assert_eq!(truncate_words("short text", 100), "short text");
+    }
+
+    #[test]
+    fn truncate_words_cuts_at_a_space() {
+        assert_eq!(truncate_words("one two three", 9), "one two");
+        assert_eq!(truncate_words("one two three", 7), "one two");
+        assert_eq!(truncate_words("one two three", 6), "one");
+        assert_eq!(truncate_words("unbroken", 4), "unbr");
+    }
+
+    #[test]
+    fn truncate_words_counts_characters_not_bytes() {
+        assert_eq!(truncate_words("été à Noël", 6), "été à");
+        assert_eq!(truncate_words("ééééé", 3), "ééé");
+    }
+
+    #[test]
+    fn stats_rounds_reading_time_up() {
+        assert_eq!(stats("").reading_time_minutes, 0);
+        let stats = stats(&"word ".repeat(201));
+        assert_eq!(stats.word_count, 201);
+        assert_eq!(stats.reading_time_minutes, 2);
+        assert!(stats.excerpt.chars().count() <= EXCERPT_MAX_CHARS);
+    }
+}
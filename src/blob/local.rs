@@ -0,0 +1,72 @@
+use super::{BlobError, BlobFuture, BlobStore};
+use actix_web::web;
+use std::path::PathBuf;
+
+/// Writes blobs as files under a directory, served by the `/uploads` route.
+pub struct LocalBlobStore {
+    dir: PathBuf,
+    public_url: String,
+}
+
+impl LocalBlobStore {
+    pub fn new(dir: PathBuf, public_url: String) -> Self {
+        LocalBlobStore {
+            dir,
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, BlobError> {
+        if key.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(BlobError(format!("invalid key {:?}", key)));
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put<'a>(&'a self, key: &'a str, _content_type: &'a str, bytes: Vec<u8>) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            blocking(move || {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, bytes)
+            })
+            .await
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            blocking(move || match std::fs::remove_file(path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            })
+            .await
+        })
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    fn key(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.public_url)?
+            .strip_prefix('/')
+            .map(str::to_string)
+    }
+}
+
+/// Runs file system calls on the blocking thread pool.
+async fn blocking<F>(f: F) -> Result<(), BlobError>
+where
+    F: FnOnce() -> std::io::Result<()> + Send + 'static,
+{
+    match web::block(f).await {
+        Ok(result) => result.map_err(|e| BlobError(e.to_string())),
+        Err(e) => Err(BlobError(e.to_string())),
+    }
+}
@@ -0,0 +1,73 @@
+//! Storage for uploaded files (avatars...). Blobs are written under a key
+//! such as `avatars/7/<uuid>-256.png` and served from a public URL, either
+//! by this server (`LocalBlobStore`) or by an S3-compatible service.
+
+mod local;
+mod s3;
+
+pub use local::LocalBlobStore;
+pub use s3::S3BlobStore;
+
+use actix_web::web;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct BlobError(String);
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blob storage failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+pub type BlobFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BlobError>> + Send + 'a>>;
+
+pub trait BlobStore: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, content_type: &'a str, bytes: Vec<u8>) -> BlobFuture<'a, ()>;
+
+    /// Deleting a missing blob is not an error.
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()>;
+
+    /// The public URL a blob is served from.
+    fn url(&self, key: &str) -> String;
+
+    /// The key of a blob from its public URL, `None` for URLs of other origins.
+    fn key(&self, url: &str) -> Option<String>;
+}
+
+/// Where `LocalBlobStore` writes, from `BLOB_DIR` (`uploads` by default).
+fn blob_dir() -> String {
+    std::env::var("BLOB_DIR").unwrap_or_else(|_| "uploads".to_string())
+}
+
+/// Picks the store from `BLOB_STORE` (`local` or `s3`).
+///
+/// `local` writes to `BLOB_DIR` and serves files from `/uploads`, under
+/// `BLOB_PUBLIC_URL` (`$API_URL/uploads` by default). `s3` reads `S3_ENDPOINT`,
+/// `S3_BUCKET`, `S3_REGION`, `S3_ACCESS_KEY_ID`, `S3_SECRET_ACCESS_KEY` and
+/// optionally `S3_PUBLIC_URL`.
+pub fn from_env() -> Arc<dyn BlobStore> {
+    match std::env::var("BLOB_STORE").as_deref() {
+        Ok("s3") => Arc::new(S3BlobStore::from_env()),
+        _ => {
+            let public_url = std::env::var("BLOB_PUBLIC_URL").unwrap_or_else(|_| {
+                let api_url =
+                    std::env::var("API_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+                format!("{}/uploads", api_url)
+            });
+            Arc::new(LocalBlobStore::new(blob_dir().into(), public_url))
+        }
+    }
+}
+
+/// Serves the files of `LocalBlobStore` when it is the configured store.
+pub fn register(config: &mut web::ServiceConfig) {
+    if !matches!(std::env::var("BLOB_STORE").as_deref(), Ok("s3")) {
+        config.service(actix_files::Files::new("/uploads", blob_dir()));
+    }
+}
@@ -0,0 +1,135 @@
+use super::{BlobError, BlobFuture, BlobStore};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Stores blobs in a bucket of an S3-compatible service (AWS, MinIO...),
+/// addressed path-style so a local MinIO works without DNS setup. Requests
+/// are signed with AWS Signature Version 4.
+pub struct S3BlobStore {
+    endpoint: url::Url,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    public_url: String,
+    http: reqwest::Client,
+}
+
+impl S3BlobStore {
+    pub fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).unwrap_or_else(|_| panic!("{} must be set", key));
+        let endpoint = var("S3_ENDPOINT");
+        let bucket = var("S3_BUCKET");
+        let public_url = std::env::var("S3_PUBLIC_URL")
+            .unwrap_or_else(|_| format!("{}/{}", endpoint.trim_end_matches('/'), bucket));
+        S3BlobStore {
+            endpoint: endpoint.parse().expect("S3_ENDPOINT must be a valid URL"),
+            bucket,
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            access_key_id: var("S3_ACCESS_KEY_ID"),
+            secret_access_key: var("S3_SECRET_ACCESS_KEY"),
+            public_url: public_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// The path of an object, each segment percent-encoded as SigV4 expects.
+    fn object_path(&self, key: &str) -> String {
+        let mut path = self.endpoint.path().trim_end_matches('/').to_string();
+        for segment in std::iter::once(self.bucket.as_str()).chain(key.split('/')) {
+            path.push('/');
+            path.push_str(&uri_encode(segment));
+        }
+        path
+    }
+
+    async fn send(&self, method: reqwest::Method, key: &str, content_type: Option<&str>, body: Vec<u8>) -> Result<(), BlobError> {
+        let path = self.object_path(key);
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, host, payload_hash, amz_date, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = [self.region.as_str(), "s3", "aws4_request"].iter().fold(
+            hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), date.as_bytes()),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.access_key_id, scope, signature
+        );
+
+        let mut request = self
+            .http
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+            .body(body);
+        if let Some(content_type) = content_type {
+            request = request.header(reqwest::header::CONTENT_TYPE, content_type);
+        }
+        let response = request.send().await.map_err(|e| BlobError(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(BlobError(format!("{} {}", status, body)));
+        }
+        Ok(())
+    }
+}
+
+impl BlobStore for S3BlobStore {
+    fn put<'a>(&'a self, key: &'a str, content_type: &'a str, bytes: Vec<u8>) -> BlobFuture<'a, ()> {
+        Box::pin(self.send(reqwest::Method::PUT, key, Some(content_type), bytes))
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(self.send(reqwest::Method::DELETE, key, None, Vec::new()))
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    fn key(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.public_url)?
+            .strip_prefix('/')
+            .map(str::to_string)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986.
+fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
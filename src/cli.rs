@@ -0,0 +1,89 @@
+use crate::admin::AdminArgs;
+use crate::db::DbPool;
+use crate::migrations::{self, MigrationError};
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(about = "RealWorld GraphQL API server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (the default when no command is given)
+    Serve(ServeArgs),
+    /// Manage the database schema with the migrations embedded in this binary
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Manage users and content without going through the API
+    Admin(AdminArgs),
+}
+
+#[derive(Args, Default)]
+pub struct ServeArgs {
+    /// Apply pending migrations before accepting requests
+    #[arg(long, env = "AUTO_MIGRATE")]
+    pub migrate: bool,
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Apply every pending migration
+    Up,
+    /// Revert the most recently applied migrations
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// List embedded migrations and whether they are applied
+    Status,
+    /// Revert then re-apply the most recently applied migrations
+    Redo {
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+}
+
+pub fn migrate(pool: &DbPool, command: MigrateCommand) -> Result<(), MigrationError> {
+    let conn = pool.get().expect("Failed to connect to the database");
+    match command {
+        MigrateCommand::Up => {
+            let applied = migrations::with_lock(&conn, || migrations::run_pending(&conn))?;
+            if applied.is_empty() {
+                println!("Database is up to date");
+            }
+            for migration in applied {
+                println!("Applied {}", migration);
+            }
+        }
+        MigrateCommand::Down { steps } => {
+            let reverted = migrations::with_lock(&conn, || migrations::revert(&conn, steps))?;
+            for migration in reverted {
+                println!("Reverted {}", migration);
+            }
+        }
+        MigrateCommand::Redo { steps } => {
+            let redone = migrations::with_lock(&conn, || migrations::redo(&conn, steps))?;
+            for migration in redone {
+                println!("Redone {}", migration);
+            }
+        }
+        MigrateCommand::Status => {
+            for (migration, applied) in migrations::status(&conn)? {
+                println!("[{}] {}", if applied { "X" } else { " " }, migration);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reports a failed command on stderr and exits with a non-zero status.
+pub fn exit_on_error<E: std::fmt::Display>(result: Result<(), E>) -> std::io::Result<()> {
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
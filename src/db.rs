@@ -8,5 +8,13 @@ pub fn get_db_pool() -> DbPool {
     dotenv().ok();
     let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let manager = ConnectionManager::<PgConnection>::new(db_url);
-    Pool::new(manager).expect("Failed to create DB Pool")
+    // Connections are opened lazily so the process can start (and report
+    // itself as not ready) while the database is still unreachable.
+    Pool::builder().build_unchecked(manager)
+}
+
+sql_function! {
+    /// Postgres `lower()`, for matching usernames and emails case-insensitively
+    /// against the `lower(...)` unique indexes on `users`.
+    fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text;
 }
@@ -1,3 +1,29 @@
+table! {
+    article_assets (id) {
+        id -> Int4,
+        article_id -> Nullable<Int4>,
+        uploader_id -> Nullable<Int4>,
+        key -> Varchar,
+        content_type -> Varchar,
+        size_bytes -> Int4,
+        is_cover -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    article_revisions (id) {
+        id -> Int4,
+        article_id -> Int4,
+        number -> Int4,
+        title -> Varchar,
+        description -> Nullable<Varchar>,
+        body -> Varchar,
+        editor_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     articles (id) {
         id -> Int4,
@@ -8,6 +34,24 @@ table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         author_id -> Int4,
+        word_count -> Int4,
+        reading_time_minutes -> Int4,
+        excerpt -> Varchar,
+        status -> Varchar,
+        published_at -> Nullable<Timestamptz>,
+        publish_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    email_verification_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        email -> Varchar,
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -19,6 +63,114 @@ table! {
     }
 }
 
+table! {
+    login_attempts (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        login -> Varchar,
+        ip -> Nullable<Varchar>,
+        succeeded -> Bool,
+        failure_reason -> Nullable<Varchar>,
+        attempted_at -> Timestamptz,
+    }
+}
+
+table! {
+    mfa_challenges (id) {
+        id -> Int4,
+        user_id -> Int4,
+        challenge_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        failed_attempts -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    mfa_recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    oidc_auth_requests (id) {
+        id -> Int4,
+        state_hash -> Varchar,
+        provider -> Varchar,
+        nonce -> Varchar,
+        code_verifier -> Varchar,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    oidc_link_requests (id) {
+        id -> Int4,
+        token_hash -> Varchar,
+        user_id -> Int4,
+        issuer -> Varchar,
+        subject -> Varchar,
+        email -> Nullable<Varchar>,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    personal_access_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        expires_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    role_changes (id) {
+        id -> Int4,
+        actor_id -> Nullable<Int4>,
+        target_id -> Nullable<Int4>,
+        old_role -> Varchar,
+        new_role -> Varchar,
+        changed_at -> Timestamptz,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
 table! {
     tag_article (tag, article_id) {
         tag -> Varchar,
@@ -40,6 +192,26 @@ table! {
     }
 }
 
+table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        issuer -> Varchar,
+        subject -> Varchar,
+        email -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    user_restrictions (user_id, target_id, kind) {
+        user_id -> Int4,
+        target_id -> Int4,
+        kind -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
@@ -48,20 +220,58 @@ table! {
         bio -> Nullable<Text>,
         image -> Nullable<Varchar>,
         password_hash -> Varchar,
+        disabled_at -> Nullable<Timestamptz>,
+        role -> Varchar,
+        sessions_revoked_at -> Nullable<Timestamptz>,
+        email_verified_at -> Nullable<Timestamptz>,
+        pending_email -> Nullable<Varchar>,
+        failed_login_attempts -> Int4,
+        locked_until -> Nullable<Timestamptz>,
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamptz>,
+        totp_last_used_step -> Nullable<Int8>,
+        profile_visibility -> Varchar,
     }
 }
 
+joinable!(article_assets -> articles (article_id));
+joinable!(article_assets -> users (uploader_id));
+joinable!(article_revisions -> articles (article_id));
+joinable!(article_revisions -> users (editor_id));
 joinable!(articles -> users (author_id));
+joinable!(email_verification_tokens -> users (user_id));
+joinable!(login_attempts -> users (user_id));
+joinable!(mfa_challenges -> users (user_id));
+joinable!(mfa_recovery_codes -> users (user_id));
+joinable!(oidc_link_requests -> users (user_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(personal_access_tokens -> users (user_id));
+joinable!(sessions -> users (user_id));
 joinable!(tag_article -> articles (article_id));
 joinable!(tag_article -> tags (tag));
 joinable!(user_favorites_article -> articles (article_id));
 joinable!(user_favorites_article -> users (user_id));
+joinable!(user_identities -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    article_assets,
+    article_revisions,
     articles,
+    email_verification_tokens,
     follows,
+    login_attempts,
+    mfa_challenges,
+    mfa_recovery_codes,
+    oidc_auth_requests,
+    oidc_link_requests,
+    password_reset_tokens,
+    personal_access_tokens,
+    role_changes,
+    sessions,
     tag_article,
     tags,
     user_favorites_article,
+    user_identities,
+    user_restrictions,
     users,
 );
@@ -0,0 +1,13 @@
+use juniper::{graphql_value, FieldError};
+use std::fmt::Display;
+
+/// Logs an unexpected error and hides its details from the client.
+pub fn internal_server_error<E: Display>(e: E) -> FieldError {
+    tracing::error!(error = %e, "internal server error");
+    FieldError::new(
+        "Internal Server Error",
+        graphql_value!({
+            "code": "internal.server.error"
+        }),
+    )
+}
@@ -0,0 +1,157 @@
+use crate::upload::{UploadedFile, Uploads};
+use actix_web::error::JsonPayloadError;
+use actix_web::http::Method;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::StreamExt;
+use juniper::http::{GraphQLBatchRequest, GraphQLRequest};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GetGraphQLRequest {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<String>,
+}
+
+/// Reads a GraphQL request from the query string (GET) or the body (POST),
+/// accepting the same shapes as `juniper_actix` plus multipart requests,
+/// whose files are kept in `uploads`.
+pub async fn parse_request(
+    req: &HttpRequest,
+    payload: web::Payload,
+    uploads: &Uploads,
+) -> Result<GraphQLBatchRequest, Error> {
+    match *req.method() {
+        Method::GET => {
+            let get_req = web::Query::<GetGraphQLRequest>::from_query(req.query_string())?;
+            let GetGraphQLRequest {
+                query,
+                operation_name,
+                variables,
+            } = get_req.into_inner();
+            let variables = variables
+                .map(|s| serde_json::from_str(&s))
+                .transpose()
+                .map_err(JsonPayloadError::Deserialize)?;
+            Ok(GraphQLBatchRequest::Single(GraphQLRequest::new(
+                query,
+                operation_name,
+                variables,
+            )))
+        }
+        Method::POST if req.content_type() == "multipart/form-data" => {
+            parse_multipart(req, payload, uploads).await
+        }
+        Method::POST => {
+            let body = String::from_request(req, &mut payload.into_inner()).await?;
+            match req.content_type() {
+                "application/json" => Ok(serde_json::from_str::<GraphQLBatchRequest>(&body)
+                    .map_err(JsonPayloadError::Deserialize)?),
+                "application/graphql" => Ok(GraphQLBatchRequest::Single(GraphQLRequest::new(
+                    body, None, None,
+                ))),
+                _ => Err(JsonPayloadError::ContentType.into()),
+            }
+        }
+        _ => Err(actix_web::error::ErrorMethodNotAllowed("method not allowed")),
+    }
+}
+
+/// The most a multipart request may carry, from `UPLOAD_MAX_BYTES` (10 MiB by default).
+fn upload_max_bytes() -> usize {
+    std::env::var("UPLOAD_MAX_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(10 * 1024 * 1024)
+}
+
+/// Reads the `operations` and `map` parts of a multipart request, keeps
+/// every other part as an upload and points the variables listed in `map`
+/// at their part.
+async fn parse_multipart(
+    req: &HttpRequest,
+    payload: web::Payload,
+    uploads: &Uploads,
+) -> Result<GraphQLBatchRequest, Error> {
+    let max_bytes = upload_max_bytes();
+    let mut multipart = actix_multipart::Multipart::new(req.headers(), payload.into_inner());
+    let mut operations = None;
+    let mut map = HashMap::<String, Vec<String>>::new();
+    let mut total_bytes = 0;
+    while let Some(field) = multipart.next().await {
+        let mut field = field?;
+        let name = field.name().unwrap_or_default().to_string();
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(str::to_string);
+        let content_type = field.content_type().map(|mime| mime.essence_str().to_string());
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            total_bytes += chunk.len();
+            if total_bytes > max_bytes {
+                return Err(actix_web::error::ErrorPayloadTooLarge("upload too large"));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        match name.as_str() {
+            "operations" => {
+                operations = Some(
+                    serde_json::from_slice::<Value>(&bytes).map_err(JsonPayloadError::Deserialize)?,
+                )
+            }
+            "map" => map = serde_json::from_slice(&bytes).map_err(JsonPayloadError::Deserialize)?,
+            _ => uploads.insert(
+                name,
+                UploadedFile {
+                    filename,
+                    content_type,
+                    bytes,
+                },
+            ),
+        }
+    }
+    let mut operations = operations
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("missing operations part"))?;
+    for (name, paths) in map {
+        for path in paths {
+            let target = path
+                .split('.')
+                .try_fold(&mut operations, |value, key| match value {
+                    Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get_mut(i)),
+                    Value::Object(fields) => fields.get_mut(key),
+                    _ => None,
+                })
+                .ok_or_else(|| actix_web::error::ErrorBadRequest(format!("invalid map path {}", path)))?;
+            *target = Value::String(name.clone());
+        }
+    }
+    Ok(serde_json::from_value::<GraphQLBatchRequest>(operations)
+        .map_err(JsonPayloadError::Deserialize)?)
+}
+
+/// Stamps `extensions.requestId` on every error of a single or batched response.
+pub fn add_request_id(response: &mut Value, request_id: &str) {
+    let responses = match response {
+        Value::Array(responses) => responses.iter_mut().collect::<Vec<_>>(),
+        response => vec![response],
+    };
+    for response in responses {
+        let errors = response.get_mut("errors").and_then(Value::as_array_mut);
+        for error in errors.into_iter().flatten() {
+            if let Some(error) = error.as_object_mut() {
+                let extensions = error
+                    .entry("extensions")
+                    .or_insert_with(|| Value::Object(Default::default()));
+                if let Some(extensions) = extensions.as_object_mut() {
+                    extensions.insert("requestId".to_string(), Value::from(request_id));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::db::DbPool;
+use crate::migrations;
+use actix_web::{web, HttpResponse};
+use diesel::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use std::time::Duration;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Check {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pending: Vec<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Check {
+            status: "ok",
+            error: None,
+            pending: vec![],
+        }
+    }
+
+    fn failed(error: String) -> Self {
+        Check {
+            status: "failed",
+            error: Some(error),
+            pending: vec![],
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+#[derive(Serialize)]
+struct Checks {
+    database: Check,
+    migrations: Check,
+}
+
+fn run_checks(pool: &DbPool) -> Checks {
+    let conn = match pool.get_timeout(CHECK_TIMEOUT) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return Checks {
+                database: Check::failed(e.to_string()),
+                migrations: Check::failed("database unavailable".to_string()),
+            }
+        }
+    };
+    let database = match diesel::sql_query("SELECT 1").execute(&conn) {
+        Ok(_) => Check::ok(),
+        Err(e) => Check::failed(e.to_string()),
+    };
+    let migrations = match migrations::pending(&conn) {
+        Ok(pending) if pending.is_empty() => Check::ok(),
+        Ok(pending) => Check {
+            status: "failed",
+            error: Some("pending migrations".to_string()),
+            pending: pending
+                .iter()
+                .map(|migration| format!("{}_{}", migration.version, migration.name))
+                .collect(),
+        },
+        Err(e) => Check::failed(e.to_string()),
+    };
+    Checks {
+        database,
+        migrations,
+    }
+}
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the database answers and every embedded migration is applied.
+pub async fn readyz(pool: web::Data<DbPool>) -> HttpResponse {
+    let pool = pool.get_ref().to_owned();
+    let checks = match web::block(move || run_checks(&pool)).await {
+        Ok(checks) => checks,
+        Err(e) => {
+            tracing::error!(error = %e, "readiness checks could not run");
+            return HttpResponse::ServiceUnavailable().json(json!({ "status": "unavailable" }));
+        }
+    };
+    let ready = checks.database.is_ok() && checks.migrations.is_ok();
+    let body = json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "checks": checks,
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+pub fn register(config: &mut web::ServiceConfig) {
+    config
+        .service(web::resource("/healthz").route(web::get().to(healthz)))
+        .service(web::resource("/readyz").route(web::get().to(readyz)));
+}
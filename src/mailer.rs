@@ -0,0 +1,151 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct MailerError(String);
+
+impl fmt::Display for MailerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to send email: {}", self.0)
+    }
+}
+
+impl std::error::Error for MailerError {}
+
+/// Delivers transactional emails (password resets, verification links...).
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), MailerError>;
+}
+
+/// Sends through an SMTP relay, e.g. a local MailHog/Mailpit during development.
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(from: Mailbox, host: &str, port: u16, credentials: Option<Credentials>) -> Self {
+        let mut builder = SmtpTransport::builder_dangerous(host).port(port);
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+        SmtpMailer {
+            from,
+            transport: builder.build(),
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), MailerError> {
+        let to = email
+            .to
+            .parse::<Mailbox>()
+            .map_err(|e| MailerError(e.to_string()))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject.clone())
+            .body(email.body.clone())
+            .map_err(|e| MailerError(e.to_string()))?;
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| MailerError(e.to_string()))
+    }
+}
+
+/// Writes every email as a JSON file in a directory, so tests can read them back.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        FileMailer { dir }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), MailerError> {
+        fs::create_dir_all(&self.dir).map_err(|e| MailerError(e.to_string()))?;
+        let file_name = format!(
+            "{}-{}.json",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            uuid::Uuid::new_v4()
+        );
+        let contents = serde_json::json!({
+            "to": email.to,
+            "subject": email.subject,
+            "body": email.body,
+        });
+        fs::write(self.dir.join(file_name), contents.to_string())
+            .map_err(|e| MailerError(e.to_string()))
+    }
+}
+
+/// Only logs that an email would have been sent. Used when nothing is configured.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<(), MailerError> {
+        tracing::info!(to = %email.to, subject = %email.subject, "email not sent, no mailer configured");
+        Ok(())
+    }
+}
+
+/// Picks the mailer from `MAILER` (`smtp`, `file` or `log`).
+///
+/// `smtp` reads `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME` and `SMTP_PASSWORD`,
+/// `file` writes to `MAIL_DIR`. The sender is `MAIL_FROM`.
+pub fn from_env() -> Arc<dyn Mailer> {
+    let from = std::env::var("MAIL_FROM")
+        .unwrap_or_else(|_| "Conduit <no-reply@localhost>".to_string())
+        .parse::<Mailbox>()
+        .expect("MAIL_FROM must be a valid mailbox");
+    match std::env::var("MAILER").as_deref() {
+        Ok("smtp") => {
+            let host = std::env::var("SMTP_HOST").unwrap_or_else(|_| "localhost".to_string());
+            let port = std::env::var("SMTP_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(1025);
+            let credentials = match (std::env::var("SMTP_USERNAME"), std::env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+                _ => None,
+            };
+            Arc::new(SmtpMailer::new(from, &host, port, credentials))
+        }
+        Ok("file") => {
+            let dir = std::env::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string());
+            Arc::new(FileMailer::new(PathBuf::from(dir)))
+        }
+        _ => Arc::new(LogMailer),
+    }
+}
+
+/// Sends an email on a background thread, so that the time taken by a request
+/// does not reveal whether an email was sent.
+pub fn send_in_background(mailer: Arc<dyn Mailer>, email: Email) {
+    std::thread::spawn(move || {
+        if let Err(e) = mailer.send(&email) {
+            tracing::error!(error = %e, to = %email.to, "email delivery failed");
+        }
+    });
+}
+
+/// Public base URL of the web client, used to build links in emails.
+pub fn app_url() -> String {
+    std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:4100".to_string())
+}
@@ -5,35 +5,104 @@ extern crate juniper;
 extern crate slugify;
 
 use actix_web::{
-    middleware,
+    dev::Service,
+    http::header::{HeaderName, HeaderValue},
     web::{self, Data},
-    App, Error, HttpResponse, HttpServer,
+    App, Error, HttpMessage, HttpResponse, HttpServer,
 };
 use actix_web_httpauth::extractors::bearer::BearerAuth;
+use clap::Parser;
+use cli::{Cli, Command, ServeArgs};
 use db::DbPool;
-use juniper_actix::{graphiql_handler, graphql_handler, playground_handler};
+use juniper_actix::{graphiql_handler, playground_handler};
+use request_id::{RequestId, REQUEST_ID_HEADER};
 use schema::Context;
+use std::time::Instant;
+use tracing::{Instrument, Span};
 
+mod admin;
 mod article;
+mod blob;
+mod cli;
+#[allow(non_local_definitions)] // diesel 1.4 derives and macros
 mod db;
+#[allow(non_local_definitions)] // diesel 1.4 derives and macros
 mod db_schema;
+mod errors;
+mod graphql;
+mod health;
+mod mailer;
+#[allow(non_local_definitions)] // diesel 1.4 derives and macros
+mod migrations;
+mod oidc;
+mod pagination;
+mod rate_limit;
+mod request_id;
 mod schema;
+mod telemetry;
+mod upload;
 mod user;
 
 use crate::schema::{create_schema, Schema};
 
+/// Shared service registered with `App::app_data` in `serve`.
+fn app_data<T: ?Sized + 'static>(req: &actix_web::HttpRequest) -> std::sync::Arc<T> {
+    req.app_data::<Data<T>>()
+        .expect("service not registered")
+        .clone()
+        .into_inner()
+}
+
 pub async fn graphql(
     req: actix_web::HttpRequest,
     payload: actix_web::web::Payload,
     pool: web::Data<DbPool>,
     schema: web::Data<Schema>,
     credentials: Option<BearerAuth>,
+    request_id: RequestId,
 ) -> Result<HttpResponse, Error> {
+    let started = Instant::now();
+    let token = credentials.map(|auth| auth.token().to_string());
+    let viewer_id = token
+        .as_deref()
+        .and_then(|token| user::auth::decode_token(token).ok())
+        .map(|data| data.claims.sub);
     let ctx = Context {
         db_pool: pool.get_ref().to_owned(),
-        token: credentials.map(|auth| auth.token().to_string()),
+        token,
+        request_id: request_id.0.clone(),
+        mailer: app_data::<dyn mailer::Mailer>(&req),
+        login_rate_limiter: app_data::<rate_limit::LoginRateLimiter>(&req),
+        client_ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        user_agent: user::sessions::user_agent(&req),
+        follow_loader: Default::default(),
+        blob_store: app_data::<dyn blob::BlobStore>(&req),
+        uploads: Default::default(),
+    };
+    let gql_request = graphql::parse_request(&req, payload, &ctx.uploads).await?;
+    let operation_name = gql_request
+        .operation_names()
+        .into_iter()
+        .map(|name| name.unwrap_or("anonymous"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let gql_response = gql_request.execute(&schema, &ctx).await;
+    let ok = gql_response.is_ok();
+    let mut body = serde_json::to_value(&gql_response)?;
+    graphql::add_request_id(&mut body, &request_id.0);
+    tracing::info!(
+        operation_name = %operation_name,
+        viewer_id = ?viewer_id,
+        latency_ms = started.elapsed().as_millis() as u64,
+        ok,
+        "graphql request completed"
+    );
+    let mut response = if ok {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::BadRequest()
     };
-    graphql_handler(&schema, &ctx, req, payload).await
+    Ok(response.json(body))
 }
 
 async fn graphiql_route() -> Result<HttpResponse, Error> {
@@ -52,19 +121,87 @@ pub fn register(config: &mut web::ServiceConfig) {
                 .route(web::get().to(graphql)),
         )
         .service(web::resource("/playground").route(web::get().to(playground_route)))
-        .service(web::resource("/graphiql").route(web::get().to(graphiql_route)));
+        .service(web::resource("/graphiql").route(web::get().to(graphiql_route)))
+        .configure(health::register)
+        .configure(blob::register)
+        .configure(oidc::register);
+}
+
+async fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({ "error": "not found" }))
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+/// How long in-flight requests may take to finish once SIGTERM/SIGINT is received.
+fn shutdown_timeout() -> u64 {
+    std::env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(30)
+}
+
+fn main() -> std::io::Result<()> {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let _telemetry = telemetry::init();
     let db_pool = db::get_db_pool();
+    match cli.command.unwrap_or(Command::Serve(ServeArgs::default())) {
+        Command::Serve(args) => {
+            if args.migrate {
+                let conn = db_pool.get().expect("Failed to connect to the database");
+                migrations::with_lock(&conn, || migrations::run_pending(&conn))
+                    .map_err(std::io::Error::other)?;
+            }
+            actix_web::rt::System::new().block_on(serve(db_pool))
+        }
+        Command::Migrate(command) => cli::exit_on_error(cli::migrate(&db_pool, command)),
+        Command::Admin(args) => cli::exit_on_error(admin::run(&db_pool, args)),
+    }
+}
+
+async fn serve(db_pool: DbPool) -> std::io::Result<()> {
+    let mailer = mailer::from_env();
+    let blob_store = blob::from_env();
+    let oidc_providers = Data::new(oidc::client::Providers::from_env());
+    let oidc_client = Data::new(oidc::client::Client::new());
+    actix_web::rt::spawn(article::assets::sweep(db_pool.clone(), blob_store.clone()));
+    actix_web::rt::spawn(article::scheduler::run(db_pool.clone()));
+    let login_rate_limiter = Data::new(rate_limit::LoginRateLimiter::from_env());
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(db_pool.clone()))
-            .wrap(middleware::Logger::default())
+            .app_data(Data::from(mailer.clone()))
+            .app_data(Data::from(blob_store.clone()))
+            .app_data(login_rate_limiter.clone())
+            .app_data(oidc_providers.clone())
+            .app_data(oidc_client.clone())
+            .wrap_fn(|req, srv| {
+                let request_id = RequestId::from_headers(req.headers());
+                req.extensions_mut().insert(request_id.clone());
+                let span = telemetry::request_span(&req, &request_id.0);
+                let started = Instant::now();
+                let response = span.in_scope(|| srv.call(req));
+                async move {
+                    let mut response = response.await?;
+                    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                        response
+                            .headers_mut()
+                            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                    }
+                    let status = response.status().as_u16();
+                    Span::current().record("http.response.status_code", status);
+                    tracing::info!(
+                        status,
+                        latency_ms = started.elapsed().as_millis() as u64,
+                        "request completed"
+                    );
+                    Ok(response)
+                }
+                .instrument(span)
+            })
             .configure(register)
-            .default_service(web::to(|| async { "404" }))
+            .default_service(web::to(not_found))
     })
+    .shutdown_timeout(shutdown_timeout())
     .bind("127.0.0.1:8080")?
     .run()
     .await
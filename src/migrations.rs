@@ -0,0 +1,213 @@
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::result::QueryResult;
+use diesel::sql_types::{BigInt, Bool};
+use std::fmt;
+
+/// A migration from `migrations/`, compiled into the binary by `build.rs`.
+pub struct EmbeddedMigration {
+    /// Directory prefix without dashes, as recorded by `diesel_cli`.
+    pub version: &'static str,
+    pub name: &'static str,
+    pub up_sql: &'static str,
+    pub down_sql: &'static str,
+}
+
+impl fmt::Display for EmbeddedMigration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.version, self.name)
+    }
+}
+
+pub static MIGRATIONS: &[EmbeddedMigration] =
+    include!(concat!(env!("OUT_DIR"), "/embedded_migrations.rs"));
+
+/// Arbitrary key for `pg_advisory_lock`, shared by every replica of the app.
+const MIGRATION_LOCK_KEY: i64 = 0x7265_616c_776f_726c;
+
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(diesel::result::Error),
+    /// The database records a version that is not embedded in this binary,
+    /// so its `down.sql` is unknown.
+    UnknownVersion(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "{}", e),
+            MigrationError::UnknownVersion(version) => {
+                write!(f, "applied migration {} is not embedded in this binary", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<diesel::result::Error> for MigrationError {
+    fn from(e: diesel::result::Error) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+#[derive(QueryableByName)]
+struct TableExists {
+    #[sql_type = "Bool"]
+    exists: bool,
+}
+
+fn migrations_table_exists(conn: &PgConnection) -> QueryResult<bool> {
+    diesel::sql_query("SELECT to_regclass('__diesel_schema_migrations') IS NOT NULL AS exists")
+        .get_result::<TableExists>(conn)
+        .map(|row| row.exists)
+}
+
+/// Creates the bookkeeping table exactly as `diesel_cli` does, so both tools
+/// can be used against the same database.
+fn setup(conn: &PgConnection) -> QueryResult<()> {
+    conn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+            version VARCHAR(50) PRIMARY KEY NOT NULL,
+            run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );",
+    )
+}
+
+/// Versions recorded as applied, oldest first.
+pub fn applied_versions(conn: &PgConnection) -> QueryResult<Vec<String>> {
+    if !migrations_table_exists(conn)? {
+        return Ok(vec![]);
+    }
+    use self::__diesel_schema_migrations::dsl::*;
+    __diesel_schema_migrations
+        .select(version)
+        .order_by(version.asc())
+        .load::<String>(conn)
+}
+
+/// Embedded migrations that have not been applied yet, oldest first.
+#[tracing::instrument(skip_all)]
+pub fn pending(conn: &PgConnection) -> QueryResult<Vec<&'static EmbeddedMigration>> {
+    let applied = applied_versions(conn)?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.iter().any(|v| v == migration.version))
+        .collect())
+}
+
+/// Every embedded migration along with whether it has been applied.
+pub fn status(conn: &PgConnection) -> QueryResult<Vec<(&'static EmbeddedMigration, bool)>> {
+    let applied = applied_versions(conn)?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|migration| {
+            let is_applied = applied.iter().any(|v| v == migration.version);
+            (migration, is_applied)
+        })
+        .collect())
+}
+
+fn run_one(conn: &PgConnection, migration: &EmbeddedMigration) -> QueryResult<()> {
+    conn.transaction(|| {
+        conn.batch_execute(migration.up_sql)?;
+        use self::__diesel_schema_migrations::dsl::*;
+        diesel::insert_into(__diesel_schema_migrations)
+            .values(version.eq(migration.version))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+fn revert_one(conn: &PgConnection, migration: &EmbeddedMigration) -> QueryResult<()> {
+    conn.transaction(|| {
+        conn.batch_execute(migration.down_sql)?;
+        use self::__diesel_schema_migrations::dsl::*;
+        diesel::delete(__diesel_schema_migrations.filter(version.eq(migration.version)))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Applies every pending migration, oldest first, and returns them.
+#[tracing::instrument(skip_all)]
+pub fn run_pending(conn: &PgConnection) -> QueryResult<Vec<&'static EmbeddedMigration>> {
+    setup(conn)?;
+    let pending = pending(conn)?;
+    for migration in &pending {
+        tracing::info!(migration = %migration, "applying migration");
+        run_one(conn, migration)?;
+    }
+    Ok(pending)
+}
+
+/// Reverts the `steps` most recently applied migrations and returns them,
+/// newest first.
+#[tracing::instrument(skip_all, fields(steps = steps))]
+pub fn revert(
+    conn: &PgConnection,
+    steps: usize,
+) -> Result<Vec<&'static EmbeddedMigration>, MigrationError> {
+    let applied = applied_versions(conn)?;
+    let mut reverted = vec![];
+    for applied_version in applied.iter().rev().take(steps) {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.version == applied_version)
+            .ok_or_else(|| MigrationError::UnknownVersion(applied_version.clone()))?;
+        tracing::info!(migration = %migration, "reverting migration");
+        revert_one(conn, migration)?;
+        reverted.push(migration);
+    }
+    Ok(reverted)
+}
+
+/// Reverts then re-applies the `steps` most recently applied migrations.
+pub fn redo(
+    conn: &PgConnection,
+    steps: usize,
+) -> Result<Vec<&'static EmbeddedMigration>, MigrationError> {
+    let reverted = revert(conn, steps)?;
+    for migration in reverted.iter().rev() {
+        tracing::info!(migration = %migration, "applying migration");
+        run_one(conn, migration)?;
+    }
+    Ok(reverted)
+}
+
+#[derive(QueryableByName)]
+struct AdvisoryLock {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+/// Runs `f` while holding a session-level Postgres advisory lock, so that
+/// replicas starting at the same time apply migrations one after the other.
+pub fn with_lock<T, E, F>(conn: &PgConnection, f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    diesel::sql_query("SELECT pg_advisory_lock($1) IS NOT NULL AS locked")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .get_result::<AdvisoryLock>(conn)?;
+    let result = f();
+    let unlocked = diesel::sql_query("SELECT pg_advisory_unlock($1) AS locked")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .get_result::<AdvisoryLock>(conn);
+    match unlocked {
+        Ok(row) if !row.locked => tracing::warn!("migration lock was not held"),
+        Err(e) => tracing::warn!(error = %e, "failed to release migration lock"),
+        Ok(_) => {}
+    }
+    result
+}
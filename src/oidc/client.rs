@@ -0,0 +1,281 @@
+use jsonwebtoken as jwt;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long discovery documents and signing keys are reused before being
+/// fetched again. Keys are also fetched again when none matches a token,
+/// in case the provider rotated them.
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// An OpenID Connect provider, configured with `OIDC_<NAME>_ISSUER`,
+/// `OIDC_<NAME>_CLIENT_ID`, `OIDC_<NAME>_CLIENT_SECRET` and optionally
+/// `OIDC_<NAME>_SCOPES` for every name listed in `OIDC_PROVIDERS`.
+///
+/// Endpoints are discovered from the issuer, so any compliant provider
+/// works (Google, Keycloak, a local mock...). Plain OAuth2 providers without
+/// ID tokens, such as GitHub, need an OIDC bridge in front of them.
+#[derive(Clone, Debug)]
+pub struct Provider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: String,
+}
+
+pub struct Providers(HashMap<String, Provider>);
+
+impl Providers {
+    pub fn from_env() -> Self {
+        let names = std::env::var("OIDC_PROVIDERS").unwrap_or_default();
+        let providers = names
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| {
+                let var = |key: &str| {
+                    std::env::var(format!("OIDC_{}_{}", name.to_uppercase(), key)).ok()
+                };
+                let (issuer, client_id) = match (var("ISSUER"), var("CLIENT_ID")) {
+                    (Some(issuer), Some(client_id)) => (issuer, client_id),
+                    _ => {
+                        tracing::warn!(provider = %name, "OIDC provider is missing its issuer or client id");
+                        return None;
+                    }
+                };
+                let provider = Provider {
+                    name: name.clone(),
+                    issuer,
+                    client_id,
+                    client_secret: var("CLIENT_SECRET"),
+                    scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+                };
+                Some((name, provider))
+            })
+            .collect();
+        Providers(providers)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.0.get(name)
+    }
+}
+
+#[derive(Debug)]
+pub enum OidcError {
+    Http(reqwest::Error),
+    InvalidIdToken(String),
+}
+
+impl fmt::Display for OidcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OidcError::Http(e) => write!(f, "request to provider failed: {}", e),
+            OidcError::InvalidIdToken(reason) => write!(f, "invalid ID token: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OidcError {}
+
+impl From<reqwest::Error> for OidcError {
+    fn from(e: reqwest::Error) -> Self {
+        OidcError::Http(e)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Discovery {
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+/// The claims of an ID token this app uses.
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+}
+
+/// Values fetched from providers, by URL, with when they were fetched.
+type MetadataCache<T> = Mutex<HashMap<String, (T, Instant)>>;
+
+fn cached<T: Clone>(cache: &MetadataCache<T>, url: &str) -> Option<T> {
+    cache
+        .lock()
+        .unwrap()
+        .get(url)
+        .filter(|(_, fetched_at)| fetched_at.elapsed() < METADATA_TTL)
+        .map(|(value, _)| value.clone())
+}
+
+fn store<T>(cache: &MetadataCache<T>, url: String, value: T) {
+    cache.lock().unwrap().insert(url, (value, Instant::now()));
+}
+
+/// Talks to the providers. Built once and shared by the workers, so that
+/// connections and fetched metadata are reused across sign-ins.
+pub struct Client {
+    http: reqwest::Client,
+    discoveries: MetadataCache<Discovery>,
+    key_sets: MetadataCache<Jwks>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Client {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("failed to build HTTP client"),
+            discoveries: Mutex::new(HashMap::new()),
+            key_sets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn discover(&self, provider: &Provider) -> Result<Discovery, OidcError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        );
+        if let Some(discovery) = cached(&self.discoveries, &url) {
+            return Ok(discovery);
+        }
+        let discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Discovery>()
+            .await?;
+        store(&self.discoveries, url, discovery.clone());
+        Ok(discovery)
+    }
+
+    /// The provider's signing keys, from the cache unless `refresh` is set.
+    async fn key_set(&self, jwks_uri: &str, refresh: bool) -> Result<Jwks, OidcError> {
+        if !refresh {
+            if let Some(jwks) = cached(&self.key_sets, jwks_uri) {
+                return Ok(jwks);
+            }
+        }
+        let jwks = self
+            .http
+            .get(jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Jwks>()
+            .await?;
+        store(&self.key_sets, jwks_uri.to_string(), jwks.clone());
+        Ok(jwks)
+    }
+
+    /// Redeems an authorization code, proving possession of the PKCE
+    /// verifier, and returns the raw ID token.
+    pub async fn exchange_code(
+        &self,
+        provider: &Provider,
+        discovery: &Discovery,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<String, OidcError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &provider.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+        let response = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+        Ok(response.id_token)
+    }
+
+    /// Checks the ID token signature against the provider's keys, then its
+    /// issuer, audience, expiry and nonce.
+    pub async fn verify_id_token(
+        &self,
+        provider: &Provider,
+        discovery: &Discovery,
+        id_token: &str,
+        expected_nonce: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let header = jwt::decode_header(id_token)
+            .map_err(|e| OidcError::InvalidIdToken(e.to_string()))?;
+        if header.alg != jwt::Algorithm::RS256 {
+            return Err(OidcError::InvalidIdToken(format!(
+                "unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+        let signing_key = |jwks: Jwks| {
+            jwks.keys
+                .into_iter()
+                .filter(|key| key.kty == "RSA")
+                .find(|key| header.kid.is_none() || key.kid == header.kid)
+        };
+        let jwk = match signing_key(self.key_set(&discovery.jwks_uri, false).await?) {
+            Some(jwk) => jwk,
+            None => signing_key(self.key_set(&discovery.jwks_uri, true).await?)
+                .ok_or_else(|| OidcError::InvalidIdToken("no matching signing key".to_string()))?,
+        };
+        let (n, e) = match (&jwk.n, &jwk.e) {
+            (Some(n), Some(e)) => (n, e),
+            _ => return Err(OidcError::InvalidIdToken("malformed signing key".to_string())),
+        };
+        let mut validation = jwt::Validation::new(jwt::Algorithm::RS256);
+        validation.iss = Some(provider.issuer.clone());
+        validation.set_audience(&[&provider.client_id]);
+        let claims = jwt::decode::<IdTokenClaims>(
+            id_token,
+            &jwt::DecodingKey::from_rsa_components(n, e),
+            &validation,
+        )
+        .map_err(|e| OidcError::InvalidIdToken(e.to_string()))?
+        .claims;
+        if claims.nonce.as_deref() != Some(expected_nonce) {
+            return Err(OidcError::InvalidIdToken("nonce mismatch".to_string()));
+        }
+        Ok(claims)
+    }
+}
@@ -0,0 +1,180 @@
+use crate::db::DbPool;
+use crate::db_schema::{oidc_auth_requests, oidc_link_requests, user_identities, users};
+use crate::user::db::{NewUserDTO, UserEntity};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::QueryResult;
+
+#[derive(Insertable)]
+#[table_name = "oidc_auth_requests"]
+pub struct NewAuthRequestDTO {
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_identities"]
+pub struct NewUserIdentityDTO {
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+/// The identity of a pending link request and the account it targets.
+#[derive(Queryable)]
+pub struct LinkRequestEntity {
+    pub id: i32,
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "oidc_link_requests"]
+pub struct NewLinkRequestDTO {
+    pub token_hash: String,
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all, fields(provider = %new_request.provider))]
+pub fn create_auth_request(pool: &DbPool, new_request: NewAuthRequestDTO) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(oidc_auth_requests::table)
+        .values(&new_request)
+        .execute(&conn)
+        .map(|_| ())
+}
+
+/// Deletes the authorization request matching `state` and returns its nonce
+/// and PKCE verifier, so a state can only be used once. Expired requests are
+/// cleaned up along the way.
+#[tracing::instrument(skip_all, fields(provider = %given_provider))]
+pub fn take_auth_request(
+    pool: &DbPool,
+    given_state_hash: &str,
+    given_provider: &str,
+) -> QueryResult<Option<(String, String)>> {
+    use crate::db_schema::oidc_auth_requests::dsl::*;
+    let conn = pool.get().unwrap();
+    let now = Utc::now();
+    diesel::delete(oidc_auth_requests.filter(expires_at.le(now))).execute(&conn)?;
+    diesel::delete(
+        oidc_auth_requests
+            .filter(state_hash.eq(given_state_hash))
+            .filter(provider.eq(given_provider)),
+    )
+    .returning((nonce, code_verifier))
+    .get_result::<(String, String)>(&conn)
+    .optional()
+}
+
+#[tracing::instrument(skip_all)]
+pub fn get_user_by_identity(
+    pool: &DbPool,
+    given_issuer: &str,
+    given_subject: &str,
+) -> QueryResult<Option<UserEntity>> {
+    let conn = pool.get().unwrap();
+    user_identities::table
+        .inner_join(users::table)
+        .filter(user_identities::issuer.eq(given_issuer))
+        .filter(user_identities::subject.eq(given_subject))
+        .select(users::all_columns)
+        .first::<UserEntity>(&conn)
+        .optional()
+}
+
+/// Registers a user signing in with a provider for the first time. They
+/// have no password until they reset one.
+#[tracing::instrument(skip_all, fields(username = %new_user.username))]
+pub fn create_user_with_identity(
+    pool: &DbPool,
+    new_user: NewUserDTO,
+    email_verified: bool,
+    given_issuer: &str,
+    given_subject: &str,
+) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        let user = diesel::insert_into(users::table)
+            .values(&new_user)
+            .get_result::<UserEntity>(&conn)?;
+        let user = if email_verified {
+            diesel::update(users::table.filter(users::id.eq(user.id)))
+                .set(users::email_verified_at.eq(Utc::now()))
+                .get_result::<UserEntity>(&conn)?
+        } else {
+            user
+        };
+        diesel::insert_into(user_identities::table)
+            .values(&NewUserIdentityDTO {
+                user_id: user.id,
+                issuer: given_issuer.to_string(),
+                subject: given_subject.to_string(),
+                email: Some(user.email.clone()),
+            })
+            .execute(&conn)?;
+        Ok(user)
+    })
+}
+
+#[tracing::instrument(skip_all, fields(user_id = new_request.user_id))]
+pub fn create_link_request(pool: &DbPool, new_request: NewLinkRequestDTO) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(oidc_link_requests::table)
+        .values(&new_request)
+        .execute(&conn)
+        .map(|_| ())
+}
+
+/// An unused, unexpired link request.
+#[tracing::instrument(skip_all)]
+pub fn get_link_request(pool: &DbPool, given_token_hash: &str) -> QueryResult<Option<LinkRequestEntity>> {
+    use crate::db_schema::oidc_link_requests::dsl::*;
+    let conn = pool.get().unwrap();
+    oidc_link_requests
+        .filter(token_hash.eq(given_token_hash))
+        .filter(used_at.is_null())
+        .filter(expires_at.gt(Utc::now()))
+        .select((id, user_id, issuer, subject, email))
+        .first::<LinkRequestEntity>(&conn)
+        .optional()
+}
+
+/// Attaches the identity of a link request to its user and burns the
+/// request. Returns false if the request was used concurrently.
+#[tracing::instrument(skip_all, fields(user_id = request.user_id))]
+pub fn link_identity(pool: &DbPool, request: &LinkRequestEntity) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        let updated = diesel::update(
+            oidc_link_requests::table
+                .filter(oidc_link_requests::id.eq(request.id))
+                .filter(oidc_link_requests::used_at.is_null()),
+        )
+        .set(oidc_link_requests::used_at.eq(Utc::now()))
+        .execute(&conn)?;
+        if updated == 0 {
+            return Ok(false);
+        }
+        diesel::insert_into(user_identities::table)
+            .values(&NewUserIdentityDTO {
+                user_id: request.user_id,
+                issuer: request.issuer.clone(),
+                subject: request.subject.clone(),
+                email: request.email.clone(),
+            })
+            .on_conflict_do_nothing()
+            .execute(&conn)?;
+        Ok(true)
+    })
+}
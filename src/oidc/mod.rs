@@ -0,0 +1,376 @@
+//! "Sign in with ..." through OpenID Connect: `/auth/oidc/{provider}/authorize`
+//! redirects to the provider, which sends the browser back to
+//! `/auth/oidc/{provider}/callback`. The outcome is handed to the web client
+//! in the fragment of a redirect to `{APP_URL}/oidc/callback`:
+//!
+//! - `token=...` when signed in,
+//! - `mfaChallenge=...` when the account needs `completeMfa`,
+//! - `linkToken=...&username=...` when the email belongs to an existing
+//!   account, whose owner can confirm with `linkIdentity`,
+//! - `error=...` otherwise.
+
+pub mod client;
+#[allow(non_local_definitions)] // diesel 1.4 derives and macros
+pub mod db;
+
+use crate::db::DbPool;
+use crate::mailer;
+use crate::user;
+use crate::user::auth;
+use crate::user::db::{NewUserDTO, UserEntity};
+use crate::user::sessions::Device;
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use client::{Client, IdTokenClaims, Provider, Providers};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const AUTH_REQUEST_TTL_MINUTES: i64 = 10;
+const LINK_REQUEST_TTL_MINUTES: i64 = 15;
+
+/// Holds the `state` of a sign-in in the browser that started it, so that a
+/// callback URL can't be replayed in another browser (login CSRF).
+const STATE_COOKIE: &str = "oidc_state";
+
+/// Public base URL of this API, from `API_URL`, used for the callback URL
+/// registered with providers.
+fn api_url() -> String {
+    std::env::var("API_URL").unwrap_or_else(|_| "http://localhost:8080".to_string())
+}
+
+fn redirect_uri(provider: &Provider) -> String {
+    format!("{}/auth/oidc/{}/callback", api_url(), provider.name)
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Sends the browser back to the web client with `params` in the fragment,
+/// which never reaches a server log.
+fn app_redirect(params: &[(&str, &str)]) -> HttpResponse {
+    let fragment = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    redirect(&format!("{}/oidc/callback#{}", mailer::app_url(), fragment))
+}
+
+fn error_redirect(error: &str) -> HttpResponse {
+    app_redirect(&[("error", error)])
+}
+
+fn state_cookie(state: &str) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, state.to_string())
+        .path("/auth/oidc")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(CookieDuration::minutes(AUTH_REQUEST_TTL_MINUTES))
+        .finish()
+}
+
+/// Runs database work off the async executor.
+async fn blocking<T, F>(f: F) -> Result<T, String>
+where
+    F: FnOnce() -> diesel::QueryResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match web::block(f).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn random_token() -> String {
+    auth::generate_secret_token().0
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    data_encoding::BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()))
+}
+
+pub async fn authorize(
+    provider: web::Path<String>,
+    pool: web::Data<DbPool>,
+    providers: web::Data<Providers>,
+    client: web::Data<Client>,
+) -> HttpResponse {
+    let provider = match providers.get(&provider) {
+        Some(provider) => provider.clone(),
+        None => return HttpResponse::NotFound().json(serde_json::json!({ "error": "unknown provider" })),
+    };
+    let discovery = match client.discover(&provider).await {
+        Ok(discovery) => discovery,
+        Err(e) => {
+            tracing::error!(error = %e, provider = %provider.name, "OIDC discovery failed");
+            return error_redirect("provider_unavailable");
+        }
+    };
+    let state = random_token();
+    let nonce = random_token();
+    let code_verifier = random_token();
+    let code_challenge = pkce_challenge(&code_verifier);
+    let new_request = db::NewAuthRequestDTO {
+        state_hash: auth::hash_secret_token(&state),
+        provider: provider.name.clone(),
+        nonce: nonce.clone(),
+        code_verifier,
+        expires_at: chrono::Utc::now() + chrono::Duration::minutes(AUTH_REQUEST_TTL_MINUTES),
+    };
+    let pool = pool.get_ref().to_owned();
+    if let Err(e) = blocking(move || db::create_auth_request(&pool, new_request)).await {
+        tracing::error!(error = %e, "failed to store OIDC authorization request");
+        return error_redirect("server_error");
+    }
+    let location = url::Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", redirect_uri(&provider).as_str()),
+            ("scope", provider.scopes.as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    );
+    match location {
+        Ok(location) => {
+            let mut response = redirect(location.as_str());
+            if let Err(e) = response.add_cookie(&state_cookie(&state)) {
+                tracing::error!(error = %e, "failed to set the OIDC state cookie");
+                return error_redirect("server_error");
+            }
+            response
+        }
+        Err(e) => {
+            tracing::error!(error = %e, provider = %provider.name, "invalid authorization endpoint");
+            error_redirect("provider_unavailable")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// What to tell the web client once the provider vouched for an identity.
+enum Outcome {
+    SignedIn(user::model::User),
+    LinkRequired { link_token: String, username: String },
+    Failed(&'static str),
+}
+
+/// Completes a sign-in, then clears the state cookie whatever the outcome.
+pub async fn callback(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    query: web::Query<CallbackQuery>,
+    pool: web::Data<DbPool>,
+    providers: web::Data<Providers>,
+    client: web::Data<Client>,
+) -> HttpResponse {
+    let mut response = complete_sign_in(&req, provider, query, pool, providers, client).await;
+    if let Err(e) = response.add_removal_cookie(&state_cookie("")) {
+        tracing::warn!(error = %e, "failed to clear the OIDC state cookie");
+    }
+    response
+}
+
+async fn complete_sign_in(
+    req: &HttpRequest,
+    provider: web::Path<String>,
+    query: web::Query<CallbackQuery>,
+    pool: web::Data<DbPool>,
+    providers: web::Data<Providers>,
+    client: web::Data<Client>,
+) -> HttpResponse {
+    let provider = match providers.get(&provider) {
+        Some(provider) => provider.clone(),
+        None => return HttpResponse::NotFound().json(serde_json::json!({ "error": "unknown provider" })),
+    };
+    let CallbackQuery { code, state, error } = query.into_inner();
+    if let Some(error) = error {
+        tracing::info!(provider = %provider.name, error = %error, "provider refused sign-in");
+        return error_redirect("access_denied");
+    }
+    let (code, state) = match (code, state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return error_redirect("invalid_request"),
+    };
+    let state_hash = auth::hash_secret_token(&state);
+    let started_here = req
+        .cookie(STATE_COOKIE)
+        .is_some_and(|cookie| auth::hash_secret_token(cookie.value()) == state_hash);
+    if !started_here {
+        tracing::info!(provider = %provider.name, "OIDC callback from another browser");
+        return error_redirect("invalid_state");
+    }
+    let db_pool = pool.get_ref().to_owned();
+    let provider_name = provider.name.clone();
+    let auth_request = blocking(move || {
+        db::take_auth_request(&db_pool, &state_hash, &provider_name)
+    })
+    .await;
+    let (nonce, code_verifier) = match auth_request {
+        Ok(Some(auth_request)) => auth_request,
+        Ok(None) => return error_redirect("invalid_state"),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to load OIDC authorization request");
+            return error_redirect("server_error");
+        }
+    };
+    let claims = async {
+        let discovery = client.discover(&provider).await?;
+        let id_token = client
+            .exchange_code(&provider, &discovery, &code, &redirect_uri(&provider), &code_verifier)
+            .await?;
+        client
+            .verify_id_token(&provider, &discovery, &id_token, &nonce)
+            .await
+    }
+    .await;
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(e) => {
+            tracing::warn!(error = %e, provider = %provider.name, "OIDC sign-in failed");
+            return error_redirect("invalid_response");
+        }
+    };
+    let device = Device {
+        user_agent: user::sessions::user_agent(req),
+        ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+    };
+    let db_pool = pool.get_ref().to_owned();
+    match blocking(move || sign_in(&db_pool, &provider, claims, device)).await {
+        Ok(Outcome::SignedIn(user)) => match (user.token, user.mfa_challenge) {
+            (Some(token), _) => app_redirect(&[("token", &token)]),
+            (None, Some(mfa)) => app_redirect(&[("mfaChallenge", &mfa.challenge)]),
+            (None, None) => error_redirect("server_error"),
+        },
+        Ok(Outcome::LinkRequired {
+            link_token,
+            username,
+        }) => app_redirect(&[("linkToken", &link_token), ("username", &username)]),
+        Ok(Outcome::Failed(error)) => error_redirect(error),
+        Err(e) => {
+            tracing::error!(error = %e, "OIDC sign-in failed");
+            error_redirect("server_error")
+        }
+    }
+}
+
+/// Signs in the user linked to the identity, registers a new one, or asks
+/// the owner of an account with the same email to link it. Accounts are
+/// never linked on email alone, since not every provider verifies it.
+fn sign_in(
+    pool: &DbPool,
+    provider: &Provider,
+    claims: IdTokenClaims,
+    device: Device,
+) -> diesel::QueryResult<Outcome> {
+    if let Some(existing) = db::get_user_by_identity(pool, &provider.issuer, &claims.sub)? {
+        if existing.disabled_at.is_some() {
+            return Ok(Outcome::Failed("account_disabled"));
+        }
+        tracing::info!(user_id = existing.id, provider = %provider.name, "signed in with provider");
+        return user::resolvers::sign_in(pool, existing, device).map(Outcome::SignedIn);
+    }
+    let email = match &claims.email {
+        Some(email) => email.clone(),
+        None => return Ok(Outcome::Failed("email_required")),
+    };
+    match user::db::get_user_by_email(pool, &email) {
+        Ok(owner) => return request_link(pool, provider, &claims, owner),
+        Err(diesel::result::Error::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+    let username = available_username(pool, &claims)?;
+    let created = db::create_user_with_identity(
+        pool,
+        NewUserDTO {
+            email,
+            username,
+            password_hash: String::new(),
+        },
+        claims.email_verified,
+        &provider.issuer,
+        &claims.sub,
+    )?;
+    tracing::info!(user_id = created.id, provider = %provider.name, "registered with provider");
+    user::resolvers::sign_in(pool, created, device).map(Outcome::SignedIn)
+}
+
+fn request_link(
+    pool: &DbPool,
+    provider: &Provider,
+    claims: &IdTokenClaims,
+    owner: UserEntity,
+) -> diesel::QueryResult<Outcome> {
+    let (link_token, token_hash) = auth::generate_secret_token();
+    db::create_link_request(
+        pool,
+        db::NewLinkRequestDTO {
+            token_hash,
+            user_id: owner.id,
+            issuer: provider.issuer.clone(),
+            subject: claims.sub.clone(),
+            email: claims.email.clone(),
+            expires_at: chrono::Utc::now() + chrono::Duration::minutes(LINK_REQUEST_TTL_MINUTES),
+        },
+    )?;
+    Ok(Outcome::LinkRequired {
+        link_token,
+        username: owner.username,
+    })
+}
+
+/// The provider's preferred username (or name, or email local part) reduced
+/// to safe characters, with a number appended if it is taken.
+fn available_username(pool: &DbPool, claims: &IdTokenClaims) -> diesel::QueryResult<String> {
+    let wanted = claims
+        .preferred_username
+        .clone()
+        .or_else(|| claims.name.clone())
+        .or_else(|| {
+            claims
+                .email
+                .as_ref()
+                .and_then(|email| email.split('@').next().map(str::to_string))
+        })
+        .unwrap_or_default();
+    let mut base = wanted
+        .trim()
+        .chars()
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .take(32)
+        .collect::<String>();
+    if base.is_empty() {
+        base = "user".to_string();
+    }
+    for n in 1.. {
+        let candidate = if n == 1 { base.clone() } else { format!("{}{}", base, n) };
+        match user::db::get_user_by_username(pool, &candidate) {
+            Err(diesel::result::Error::NotFound) => return Ok(candidate),
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+    }
+    unreachable!()
+}
+
+/// Routes of the sign-in flow. `serve` registers the shared `Providers` and
+/// `Client` they use.
+pub fn register(config: &mut web::ServiceConfig) {
+    config
+        .service(web::resource("/auth/oidc/{provider}/authorize").route(web::get().to(authorize)))
+        .service(web::resource("/auth/oidc/{provider}/callback").route(web::get().to(callback)));
+}
@@ -0,0 +1,87 @@
+//! Cursor pagination for connections, which take `first` and `after`.
+//! Cursors are opaque to clients; they encode the sort key of an item.
+
+use data_encoding::BASE64URL_NOPAD;
+use juniper::{graphql_value, FieldError, GraphQLObject};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Where a page of a connection ends")]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    #[graphql(description = "Pass as `after` to get the next page")]
+    pub end_cursor: Option<String>,
+}
+
+/// The number of items to load for `first`: 20 when omitted, at most 100.
+pub fn page_size(first: Option<i32>) -> i64 {
+    first
+        .map(|first| (first as i64).clamp(0, MAX_PAGE_SIZE))
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+}
+
+pub fn encode_cursor(key: i64) -> String {
+    BASE64URL_NOPAD.encode(format!("cursor:{}", key).as_bytes())
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<i64, FieldError> {
+    BASE64URL_NOPAD
+        .decode(cursor.as_bytes())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|decoded| decoded.strip_prefix("cursor:")?.parse().ok())
+        .ok_or_else(|| {
+            FieldError::new(
+                "Invalid cursor",
+                graphql_value!({
+                    "code": "invalid.cursor"
+                }),
+            )
+        })
+}
+
+/// Splits the `page_size + 1` items loaded for a page into the page and
+/// whether there is a next one.
+pub fn split_page<T>(mut items: Vec<T>, page_size: i64) -> (Vec<T>, bool) {
+    let has_next_page = items.len() as i64 > page_size;
+    items.truncate(page_size as usize);
+    (items, has_next_page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        for key in [0, 1, 42, -7, i64::MAX] {
+            assert_eq!(decode_cursor(&encode_cursor(key)).ok(), Some(key));
+        }
+    }
+
+    #[test]
+    fn decode_cursor_refuses_garbage() {
+        assert!(decode_cursor("").is_err());
+        assert!(decode_cursor("not base64!").is_err());
+        assert!(decode_cursor(&BASE64URL_NOPAD.encode(b"42")).is_err());
+        assert!(decode_cursor(&BASE64URL_NOPAD.encode(b"cursor:")).is_err());
+        assert!(decode_cursor(&BASE64URL_NOPAD.encode(b"cursor:4x")).is_err());
+        assert!(decode_cursor(&BASE64URL_NOPAD.encode(b"\xffcursor:1")).is_err());
+    }
+
+    #[test]
+    fn page_size_defaults_and_clamps() {
+        assert_eq!(page_size(None), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(Some(5)), 5);
+        assert_eq!(page_size(Some(-3)), 0);
+        assert_eq!(page_size(Some(1_000)), MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    fn split_page_reports_a_next_page() {
+        assert_eq!(split_page(vec![1, 2, 3], 2), (vec![1, 2], true));
+        assert_eq!(split_page(vec![1, 2], 2), (vec![1, 2], false));
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many requests a key may make in a burst, refilled evenly over `period`.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    /// Reads a per-minute quota from `var`, `default` when unset or invalid.
+    pub fn per_minute_from_env(var: &str, default: u32) -> Self {
+        let capacity = std::env::var(var)
+            .ok()
+            .and_then(|capacity| capacity.parse().ok())
+            .unwrap_or(default);
+        Quota {
+            capacity,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Where token buckets live. The in-memory store only limits a single
+/// process; replicas behind a load balancer need a shared implementation.
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one token from the bucket of `key`, or returns how long to wait
+    /// until one is available.
+    fn take(&self, key: &str, quota: Quota) -> Result<(), Duration>;
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Buckets are dropped once full again, so the map only holds keys that
+/// were active recently.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimitStore for InMemoryStore {
+    fn take(&self, key: &str, quota: Quota) -> Result<(), Duration> {
+        let capacity = quota.capacity as f64;
+        let per_second = capacity / quota.period.as_secs_f64();
+        let now = Instant::now();
+        let refill = |bucket: &mut Bucket| {
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+            bucket.refilled_at = now;
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                refill(bucket);
+                bucket.tokens < capacity
+            });
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+        refill(bucket);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if per_second > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        } else {
+            Err(quota.period)
+        }
+    }
+}
+
+/// The account a login attempt targets.
+pub enum LoginAccount<'a> {
+    User(i32),
+    /// A login matching no user, lowercased; each gets its own bucket so
+    /// probing for accounts doesn't throttle real ones.
+    Unknown(&'a str),
+}
+
+/// Throttles login attempts per client IP and per account.
+pub struct LoginRateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    per_ip: Quota,
+    per_account: Quota,
+}
+
+impl LoginRateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, per_ip: Quota, per_account: Quota) -> Self {
+        LoginRateLimiter {
+            store,
+            per_ip,
+            per_account,
+        }
+    }
+
+    /// In-memory limiter with quotas from `LOGIN_RATE_LIMIT_PER_IP` and
+    /// `LOGIN_RATE_LIMIT_PER_ACCOUNT`, both attempts per minute.
+    pub fn from_env() -> Self {
+        LoginRateLimiter::new(
+            Arc::new(InMemoryStore::default()),
+            Quota::per_minute_from_env("LOGIN_RATE_LIMIT_PER_IP", 30),
+            Quota::per_minute_from_env("LOGIN_RATE_LIMIT_PER_ACCOUNT", 10),
+        )
+    }
+
+    /// Counts one attempt against both buckets. Accounts are keyed by user
+    /// id, so signing in by username or by email shares one budget.
+    pub fn check(&self, ip: Option<&str>, account: LoginAccount) -> Result<(), Duration> {
+        if let Some(ip) = ip {
+            self.store.take(&format!("login:ip:{}", ip), self.per_ip)?;
+        }
+        let key = match account {
+            LoginAccount::User(user_id) => format!("login:user:{}", user_id),
+            LoginAccount::Unknown(login) => format!("login:unknown:{}", login),
+        };
+        self.store.take(&key, self.per_account)
+    }
+}
@@ -0,0 +1,45 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::HeaderMap;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Identifies one HTTP request across log lines, spans and GraphQL errors.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Reuses the caller's `X-Request-Id` when it is sane, otherwise makes a new one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let given = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| {
+                !value.is_empty()
+                    && value.len() <= MAX_REQUEST_ID_LEN
+                    && value.chars().all(|c| c.is_ascii_graphic())
+            });
+        match given {
+            Some(value) => RequestId(value.to_string()),
+            None => RequestId(Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId::from_headers(req.headers()));
+        ready(Ok(request_id))
+    }
+}
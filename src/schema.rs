@@ -1,10 +1,26 @@
 use crate::article::resolvers::{ArticleMutation, ArticleQuery};
+use crate::blob::BlobStore;
 use crate::db::DbPool;
-use crate::user::resolvers::{UsersQuery, UsersMutation};
+use crate::mailer::Mailer;
+use crate::rate_limit::LoginRateLimiter;
+use crate::upload::Uploads;
+use crate::user::loader::FollowLoader;
+use crate::user::resolvers::{AdminMutation, AdminQuery, UsersMutation, UsersQuery};
 use juniper::{EmptySubscription, RootNode};
+use std::sync::Arc;
 pub struct Context {
     pub db_pool: DbPool,
     pub token: Option<String>,
+    pub request_id: String,
+    pub mailer: Arc<dyn Mailer>,
+    pub login_rate_limiter: Arc<LoginRateLimiter>,
+    /// Address of the connected peer, not taken from forwarding headers.
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub follow_loader: FollowLoader,
+    pub blob_store: Arc<dyn BlobStore>,
+    /// Files of a multipart request, see `upload`.
+    pub uploads: Uploads,
 }
 
 impl juniper::Context for Context {}
@@ -19,6 +35,9 @@ impl QueryRoot {
     fn articles() -> ArticleQuery {
         ArticleQuery {}
     }
+    fn admin() -> AdminQuery {
+        AdminQuery {}
+    }
 }
 
 pub struct MutationRoot;
@@ -32,6 +51,10 @@ impl MutationRoot {
     fn articles() -> ArticleMutation {
         ArticleMutation {}
     }
+
+    fn admin() -> AdminMutation {
+        AdminMutation {}
+    }
 }
 
 pub type Schema = RootNode<'static, QueryRoot, MutationRoot, EmptySubscription<Context>>;
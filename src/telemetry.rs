@@ -0,0 +1,154 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::HeaderMap;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::global;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_SERVICE_NAME: &str = "real_world_rust_graphql";
+
+/// Where finished spans are sent, picked with `OTEL_TRACES_EXPORTER`.
+///
+/// `otlp` ships them over OTLP/HTTP to the collector configured by the
+/// standard `OTEL_EXPORTER_OTLP_ENDPOINT` / `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`
+/// variables, `console` prints them to stdout, and `none` (the default) only
+/// keeps them in-process for the log output.
+enum TracesExporter {
+    Otlp,
+    Console,
+    None,
+}
+
+impl TracesExporter {
+    fn from_env() -> Self {
+        match std::env::var("OTEL_TRACES_EXPORTER").as_deref() {
+            Ok("otlp") => TracesExporter::Otlp,
+            Ok("console") | Ok("stdout") => TracesExporter::Console,
+            _ => TracesExporter::None,
+        }
+    }
+}
+
+/// Shape of the log lines written to stderr, picked with `LOG_FORMAT`.
+///
+/// `json` writes one object per line carrying the fields of every enclosing
+/// span (so each line has the `request_id`), anything else keeps the
+/// human-readable output. The level is set with `RUST_LOG` and defaults to
+/// `LOG_LEVEL`, then `info`.
+enum LogFormat {
+    Json,
+    Pretty,
+}
+
+impl LogFormat {
+    fn from_env() -> Self {
+        match std::env::var("LOG_FORMAT").as_deref() {
+            Ok("json") => LogFormat::Json,
+            _ => LogFormat::Pretty,
+        }
+    }
+}
+
+/// Flushes and shuts down the tracer provider when dropped.
+pub struct TelemetryGuard {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("failed to shut down tracer provider: {}", e);
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber and OpenTelemetry tracer provider.
+///
+/// Must be called before the actix runtime is started: the blocking OTLP
+/// client refuses to be built from inside an async context.
+pub fn init() -> TelemetryGuard {
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string());
+    let resource = Resource::builder().with_service_name(service_name).build();
+
+    let mut builder = SdkTracerProvider::builder().with_resource(resource);
+    match TracesExporter::from_env() {
+        TracesExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .build()
+                .expect("Failed to create OTLP span exporter");
+            builder = builder.with_batch_exporter(exporter);
+        }
+        TracesExporter::Console => {
+            builder = builder.with_simple_exporter(opentelemetry_stdout::SpanExporter::default());
+        }
+        TracesExporter::None => {}
+    }
+    let provider = builder.build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string()))
+    });
+    let (json_layer, pretty_layer) = match LogFormat::from_env() {
+        LogFormat::Json => (
+            Some(tracing_subscriber::fmt::layer().json().with_writer(std::io::stderr)),
+            None,
+        ),
+        LogFormat::Pretty => (
+            None,
+            Some(tracing_subscriber::fmt::layer().with_writer(std::io::stderr)),
+        ),
+    };
+    let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(json_layer)
+        .with(pretty_layer)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+
+    TelemetryGuard { provider }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Reads the W3C `traceparent`/`tracestate` headers of an incoming request.
+pub fn extract_context(headers: &HeaderMap) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Root span of an HTTP request, continuing the caller's trace if it sent one.
+pub fn request_span(req: &ServiceRequest, request_id: &str) -> Span {
+    let span = tracing::info_span!(
+        "HTTP request",
+        otel.kind = "server",
+        otel.name = %format!("{} {}", req.method(), req.path()),
+        request_id = %request_id,
+        http.request.method = %req.method(),
+        url.path = %req.path(),
+        http.response.status_code = tracing::field::Empty,
+    );
+    span.set_parent(extract_context(req.headers()));
+    span
+}
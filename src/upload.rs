@@ -0,0 +1,50 @@
+//! Files sent with a GraphQL multipart request
+//! (https://github.com/jaydenseric/graphql-multipart-request-spec). The
+//! request parser keeps the files of a request in `Uploads` and replaces
+//! each file variable by the name of its part, which is what an `Upload`
+//! argument holds; resolvers then `take` the file from the context.
+
+use juniper::{graphql_scalar, ParseScalarResult, ParseScalarValue, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub struct UploadedFile {
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct Uploads(Mutex<HashMap<String, UploadedFile>>);
+
+impl Uploads {
+    pub fn insert(&self, name: String, file: UploadedFile) {
+        self.0.lock().unwrap().insert(name, file);
+    }
+
+    /// The file of an `Upload` argument; each file can be taken once.
+    pub fn take(&self, upload: &Upload) -> Option<UploadedFile> {
+        self.0.lock().unwrap().remove(&upload.0)
+    }
+}
+
+/// A file argument, sent as a part of a multipart request.
+pub struct Upload(String);
+
+#[graphql_scalar(description = "A file sent as a part of a GraphQL multipart request")]
+impl<S> GraphQLScalar for Upload
+where
+    S: ScalarValue,
+{
+    fn resolve(&self) -> Value {
+        Value::scalar(self.0.clone())
+    }
+
+    fn from_input_value(v: &InputValue) -> Option<Upload> {
+        v.as_string_value().map(|name| Upload(name.to_string()))
+    }
+
+    fn from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, S> {
+        <String as ParseScalarValue<S>>::from_str(value)
+    }
+}
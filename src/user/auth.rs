@@ -3,6 +3,9 @@ use chrono::{Duration, prelude::*};
 use jsonwebtoken as jwt;
 use jwt::{DecodingKey, EncodingKey, decode, Validation, TokenData};
 use super::errors::UserError;
+use super::model::{Role, Scope};
+use super::sessions::{CachedSession, CachedToken};
+use crate::db::DbPool;
 use juniper::{FieldError, IntoFieldError};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -11,15 +14,33 @@ pub struct Claims {
     iat: usize, // Optional. Issued at (as UTC timestamp)
     iss: String, // Optional. Issuer
     pub sub: String, // Optional. Subject (whom token refers to)
+    #[serde(default = "default_role")]
+    pub role: String, // Role of the subject when the token was issued
+    #[serde(default)]
+    pub sid: Option<i32>, // Session the token was issued for
 }
 
-pub fn get_token(id: i32) -> String {
+fn default_role() -> String {
+    Role::User.as_str().to_string()
+}
+
+pub fn hash_password(password: String) -> String {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap()
+}
+
+/// How long a token is valid. A session is listed as active for as long as
+/// it has been used within this time.
+pub const TOKEN_TTL_MINUTES: i64 = 60;
+
+pub fn get_token(id: i32, role: Role, session_id: i32) -> String {
     let sub = id.to_string();
     let now = Utc::now();
     let iat = now.timestamp() as usize;
-    let exp = (now + Duration::minutes(60)).timestamp() as usize;
+    let exp = (now + Duration::minutes(TOKEN_TTL_MINUTES)).timestamp() as usize;
     let iss = "real_world_rust_graphql".to_string();
-    let claims = Claims { exp, iss, iat, sub };
+    let role = role.as_str().to_string();
+    let sid = Some(session_id);
+    let claims = Claims { exp, iss, iat, sub, role, sid };
     jwt::encode(&jwt::Header::default() , &claims, &EncodingKey::from_secret("real_world_rust_graphql".as_ref()))
     .expect("jwt creation failed!")
 }
@@ -28,18 +49,200 @@ pub fn decode_token(token: &str) -> jwt::errors::Result<TokenData<Claims>> {
     decode::<Claims>(token, &DecodingKey::from_secret("real_world_rust_graphql".as_ref()), &Validation::default())
 }
 
-pub fn get_id_from_token(token: &Option<String>) -> Result<i32, FieldError>{
+/// Prefix of personal access tokens, which tells them apart from JWTs and
+/// lets secret scanners recognize leaked ones.
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "cpat_";
+
+pub fn get_id_from_token(pool: &DbPool, token: &Option<String>, scope: Scope) -> Result<i32, FieldError> {
+    get_viewer_from_token(pool, token, scope).map(|viewer| viewer.id)
+}
+
+
+/// The authenticated caller of a resolver.
+pub struct Viewer {
+    pub id: i32,
+    pub role: Role,
+    /// What a personal access token was granted; `None` for a password
+    /// session, which may do everything.
+    pub scopes: Option<Vec<Scope>>,
+    /// The session of a JWT; `None` for a personal access token.
+    pub session_id: Option<i32>,
+}
+
+impl Viewer {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
+}
+
+/// Guard for privileged resolvers: the viewer's current role, as stored in
+/// the database (or cached for a few seconds, see `sessions`), must be at
+/// least `required`, so a demotion takes effect before the token expires.
+/// Personal access tokens never carry a role.
+pub fn require_role(pool: &DbPool, token: &Option<String>, required: Role) -> Result<Viewer, FieldError> {
+    let viewer = require_session(pool, token)?;
+    if viewer.role < required {
+        return Err(UserError::Forbidden.into_field_error());
+    }
+    Ok(viewer)
+}
 
-    if token.is_none() {
-        return Err(UserError::Unauthorized.into_field_error())
+/// The viewer of a JWT, or of a personal access token granted `scope`.
+pub fn get_viewer_from_token(pool: &DbPool, token: &Option<String>, scope: Scope) -> Result<Viewer, FieldError> {
+    let viewer = authenticate(pool, token)?;
+    if !viewer.has_scope(scope) {
+        return Err(UserError::InsufficientScope.into_field_error());
     }
-    let token = token.as_ref().unwrap().as_str();
-    let claims_result = decode_token(token);
-    if claims_result.is_err() {
-        return Err(UserError::Unauthorized.into_field_error())
+    Ok(viewer)
+}
+
+/// Guard for account management (passwords, 2FA, tokens...), which only a
+/// JWT from signing in may do.
+pub fn require_session(pool: &DbPool, token: &Option<String>) -> Result<Viewer, FieldError> {
+    let viewer = authenticate(pool, token)?;
+    if viewer.scopes.is_some() {
+        return Err(UserError::SessionRequired.into_field_error());
     }
-    let claims = claims_result.unwrap().claims;
-    let id = claims.sub.parse::<i32>().unwrap();
-    Ok(id)
+    Ok(viewer)
 }
 
+fn authenticate(pool: &DbPool, token: &Option<String>) -> Result<Viewer, FieldError> {
+    let token = token
+        .as_deref()
+        .ok_or_else(|| UserError::Unauthorized.into_field_error())?;
+    if token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) {
+        get_viewer_from_personal_access_token(pool, token)
+    } else {
+        get_viewer_from_jwt(pool, token)
+    }
+}
+
+/// Checks the token signature, then that its session is live: not revoked,
+/// its user still exists, is not disabled and has not revoked all their
+/// sessions since the token was issued (e.g. by resetting their password).
+/// Tokens from before sessions were recorded carry no session and are
+/// refused.
+fn get_viewer_from_jwt(pool: &DbPool, token: &str) -> Result<Viewer, FieldError> {
+    let claims = decode_token(token)
+        .map_err(|_| UserError::Unauthorized.into_field_error())?
+        .claims;
+    let id = claims
+        .sub
+        .parse::<i32>()
+        .map_err(|_| UserError::Unauthorized.into_field_error())?;
+    let session_id = claims
+        .sid
+        .ok_or_else(|| UserError::Unauthorized.into_field_error())?;
+    let cache = super::sessions::cache();
+    let session = match cache.get(session_id) {
+        Some(session) => session,
+        None => {
+            let session = check_session(pool, session_id, claims.iat as i64)?;
+            cache.insert(session_id, session);
+            session
+        }
+    };
+    if session.user_id != id {
+        return Err(UserError::Unauthorized.into_field_error());
+    }
+    Ok(Viewer {
+        id,
+        role: session.role,
+        scopes: None,
+        session_id: Some(session_id),
+    })
+}
+
+/// Loads a session that is not cached, and records that it was seen.
+fn check_session(pool: &DbPool, session_id: i32, issued_at: i64) -> Result<CachedSession, FieldError> {
+    use super::db::{get_live_session_user, touch_session};
+    let user = get_live_session_user(pool, session_id)
+        .map_err(crate::errors::internal_server_error)?
+        .ok_or_else(|| UserError::Unauthorized.into_field_error())?;
+    if user.disabled_at.is_some() {
+        return Err(UserError::Unauthorized.into_field_error());
+    }
+    if let Some(revoked_at) = user.sessions_revoked_at {
+        if issued_at < revoked_at.timestamp() {
+            return Err(UserError::Unauthorized.into_field_error());
+        }
+    }
+    if let Err(e) = touch_session(pool, session_id) {
+        tracing::warn!(error = %e, session_id, "failed to record session use");
+    }
+    Ok(CachedSession {
+        user_id: user.id,
+        role: Role::parse(&user.role),
+    })
+}
+
+/// Looks the token up by its hash, or in the cache of `sessions`. Like JWTs,
+/// tokens created before the user revoked their sessions stop working.
+fn get_viewer_from_personal_access_token(pool: &DbPool, token: &str) -> Result<Viewer, FieldError> {
+    let token_hash = hash_secret_token(token);
+    let cache = super::sessions::cache();
+    let token = match cache.get_token(&token_hash) {
+        Some(token) => token,
+        None => {
+            let token = check_personal_access_token(pool, &token_hash)?;
+            cache.insert_token(token_hash, token.clone());
+            token
+        }
+    };
+    Ok(Viewer {
+        id: token.user_id,
+        role: Role::User,
+        scopes: Some(token.scopes),
+        session_id: None,
+    })
+}
+
+/// Loads a token that is not cached, and records that it was used unless
+/// that was recorded lately.
+fn check_personal_access_token(pool: &DbPool, token_hash: &str) -> Result<CachedToken, FieldError> {
+    use super::db::{get_active_personal_access_token, touch_personal_access_token, TOKEN_TOUCH_INTERVAL_MINUTES};
+    let (token, user) = get_active_personal_access_token(pool, token_hash)
+        .map_err(crate::errors::internal_server_error)?
+        .ok_or_else(|| UserError::Unauthorized.into_field_error())?;
+    if user.disabled_at.is_some() {
+        return Err(UserError::Unauthorized.into_field_error());
+    }
+    if user
+        .sessions_revoked_at
+        .is_some_and(|revoked_at| token.created_at < revoked_at)
+    {
+        return Err(UserError::Unauthorized.into_field_error());
+    }
+    let touch_before = Utc::now() - Duration::minutes(TOKEN_TOUCH_INTERVAL_MINUTES);
+    if token.last_used_at.is_none_or(|last_used_at| last_used_at < touch_before) {
+        if let Err(e) = touch_personal_access_token(pool, token.id) {
+            tracing::warn!(error = %e, token_id = token.id, "failed to record token use");
+        }
+    }
+    Ok(CachedToken {
+        token_id: token.id,
+        user_id: user.id,
+        scopes: token.scopes.iter().filter_map(|scope| Scope::parse(scope)).collect(),
+        expires_at: token.expires_at,
+    })
+}
+
+/// Creates a random single-use secret (password reset links, ...) and
+/// returns it along with the hash to store in its place.
+pub fn generate_secret_token() -> (String, String) {
+    use rand::RngCore;
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let token_hash = hash_secret_token(&token);
+    (token, token_hash)
+}
+
+/// Secret tokens are long and random, so a plain SHA-256 is enough to keep
+/// them useless to whoever reads the database.
+pub fn hash_secret_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
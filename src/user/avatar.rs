@@ -0,0 +1,160 @@
+//! Avatar images. Uploads are checked, decoded and re-encoded as square PNG
+//! variants, which drops EXIF and other metadata (after applying the EXIF
+//! orientation, so photos stay upright).
+
+use super::errors::UserError;
+use crate::upload::UploadedFile;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+
+/// Widths of the generated variants; `User.image` points to the largest,
+/// the others share its key with their own size as suffix.
+pub const SIZES: [u32; 3] = [64, 128, 256];
+
+const ACCEPTED_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::WebP,
+    ImageFormat::Gif,
+];
+
+/// Images larger than this in either dimension are rejected before decoding.
+const MAX_DIMENSION: u32 = 4096;
+
+/// The most memory a decoder may allocate, which also bounds animated GIF
+/// and WebP frames that stay within `MAX_DIMENSION`.
+const MAX_ALLOC: u64 = 64 * 1024 * 1024;
+
+/// The largest accepted upload, from `AVATAR_MAX_BYTES` (2 MiB by default).
+fn max_bytes() -> usize {
+    std::env::var("AVATAR_MAX_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(2 * 1024 * 1024)
+}
+
+/// Checks the declared content type and the size, before any decoding.
+pub fn check(file: &UploadedFile) -> Result<(), UserError> {
+    let declared = file
+        .content_type
+        .as_deref()
+        .and_then(ImageFormat::from_mime_type);
+    if !declared.is_some_and(|format| ACCEPTED_FORMATS.contains(&format)) {
+        return Err(UserError::InvalidAvatar("Avatars must be PNG, JPEG, WebP or GIF images"));
+    }
+    if file.bytes.len() > max_bytes() {
+        return Err(UserError::InvalidAvatar("Avatar is too large"));
+    }
+    Ok(())
+}
+
+/// The PNG variants of an image, as `(size, bytes)` for every size in `SIZES`.
+pub fn variants(bytes: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, UserError> {
+    let invalid = |_| UserError::InvalidAvatar("Avatar is not a valid image");
+    let format = image::guess_format(bytes).map_err(invalid)?;
+    if !ACCEPTED_FORMATS.contains(&format) {
+        return Err(UserError::InvalidAvatar("Avatars must be PNG, JPEG, WebP or GIF images"));
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+    SIZES
+        .iter()
+        .map(|&size| {
+            let mut encoded = Vec::new();
+            image
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+                .map_err(invalid)?;
+            Ok((size, encoded))
+        })
+        .collect()
+}
+
+/// The key of a variant of an upload.
+pub fn key(user_id: i32, upload_id: &str, size: u32) -> String {
+    format!("avatars/{}/{}-{}.png", user_id, upload_id, size)
+}
+
+/// The keys of every variant of the avatar stored under `key` (any variant),
+/// or none if `key` is not an avatar of `user_id`. `users.image` can be set
+/// to any URL, so it may point to the avatar of someone else.
+pub fn variant_keys(key: &str, user_id: i32) -> Vec<String> {
+    let mut parts = key.split('/');
+    let (Some("avatars"), Some(owner_id), Some(file), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Vec::new();
+    };
+    if owner_id != user_id.to_string() {
+        return Vec::new();
+    }
+    let Some((upload_id, _)) = file.rsplit_once('-') else {
+        return Vec::new();
+    };
+    SIZES
+        .iter()
+        .map(|&size| self::key(user_id, upload_id, size))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_keys_lists_every_size() {
+        let upload_id = "0b7e6a0e-5d0c-4c39-9d1e-2f4cbb0e1c11";
+        assert_eq!(
+            variant_keys(&key(7, upload_id, 128), 7),
+            SIZES.map(|size| key(7, upload_id, size))
+        );
+    }
+
+    #[test]
+    fn variant_keys_ignores_other_keys() {
+        assert!(variant_keys("articles/7/abc-64.png", 7).is_empty());
+        assert!(variant_keys("avatars/7", 7).is_empty());
+        assert!(variant_keys("avatars/7/abc", 7).is_empty());
+        assert!(variant_keys("avatars/7/abc-64.png/more", 7).is_empty());
+        assert!(variant_keys("https://example.com/me.png", 7).is_empty());
+    }
+
+    #[test]
+    fn variant_keys_ignores_avatars_of_other_users() {
+        assert!(variant_keys(&key(8, "abc", 256), 7).is_empty());
+        assert!(variant_keys("avatars/07/abc-256.png", 7).is_empty());
+    }
+
+    #[test]
+    fn variants_are_square_pngs() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(300, 200)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let variants = variants(&png).ok().expect("a valid PNG");
+        assert_eq!(variants.iter().map(|(size, _)| *size).collect::<Vec<_>>(), SIZES);
+        for (size, bytes) in variants {
+            let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+            assert_eq!((image.width(), image.height()), (size, size));
+        }
+    }
+
+    #[test]
+    fn variants_refuses_oversized_images() {
+        let mut png = Vec::new();
+        DynamicImage::new_luma8(MAX_DIMENSION + 1, 1)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert!(variants(&png).is_err());
+        assert!(variants(b"not an image").is_err());
+    }
+}
@@ -4,8 +4,17 @@ use crate::db_schema::users;
 use crate::db_schema::users::dsl::*;
 use crate::db_schema::follows;
 use crate::db_schema::follows::dsl::*;
-use crate::db::DbPool;
+use crate::db_schema::role_changes;
+use crate::db_schema::password_reset_tokens;
+use crate::db_schema::email_verification_tokens;
+use crate::db_schema::login_attempts;
+use crate::db_schema::{mfa_challenges, mfa_recovery_codes};
+use crate::db_schema::personal_access_tokens;
+use crate::db_schema::sessions;
+use crate::db_schema::user_restrictions;
+use crate::db::{lower, DbPool};
 use diesel::pg::upsert::*;
+use chrono::{DateTime, Utc};
 
 #[derive(Queryable, PartialEq)]
 pub struct UserEntity {
@@ -15,6 +24,17 @@ pub struct UserEntity {
     pub bio: Option<String>,
     pub image: Option<String>,
     pub password_hash: String,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub role: String,
+    pub sessions_revoked_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub pending_email: Option<String>,
+    pub failed_login_attempts: i32,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    pub totp_last_used_step: Option<i64>,
+    pub profile_visibility: String,
 }
 
 
@@ -34,6 +54,129 @@ pub struct UserUpdateDTO {
     pub bio: Option<String>,
     pub image: Option<String>,
     pub password_hash: String,
+    pub profile_visibility: String,
+}
+
+#[derive(Queryable)]
+pub struct RoleChangeEntity {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub old_role: String,
+    pub new_role: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "role_changes"]
+pub struct NewRoleChangeDTO {
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub old_role: String,
+    pub new_role: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct NewPasswordResetTokenDTO {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_verification_tokens"]
+pub struct NewEmailVerificationTokenDTO {
+    pub user_id: i32,
+    pub email: String,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_attempts"]
+pub struct NewLoginAttemptDTO {
+    pub user_id: Option<i32>,
+    pub login: String,
+    pub ip: Option<String>,
+    pub succeeded: bool,
+    pub failure_reason: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "mfa_challenges"]
+pub struct NewMfaChallengeDTO {
+    pub user_id: i32,
+    pub challenge_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A personal access token as shown to its owner; the hash never leaves the database.
+#[derive(Queryable)]
+pub struct PersonalAccessTokenEntity {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+const PERSONAL_ACCESS_TOKEN_COLUMNS: (
+    personal_access_tokens::id,
+    personal_access_tokens::name,
+    personal_access_tokens::scopes,
+    personal_access_tokens::created_at,
+    personal_access_tokens::last_used_at,
+    personal_access_tokens::expires_at,
+) = (
+    personal_access_tokens::id,
+    personal_access_tokens::name,
+    personal_access_tokens::scopes,
+    personal_access_tokens::created_at,
+    personal_access_tokens::last_used_at,
+    personal_access_tokens::expires_at,
+);
+
+#[derive(Insertable)]
+#[table_name = "personal_access_tokens"]
+pub struct NewPersonalAccessTokenDTO {
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Queryable)]
+pub struct SessionEntity {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+const SESSION_COLUMNS: (
+    sessions::id,
+    sessions::user_agent,
+    sessions::ip,
+    sessions::created_at,
+    sessions::last_seen_at,
+) = (
+    sessions::id,
+    sessions::user_agent,
+    sessions::ip,
+    sessions::created_at,
+    sessions::last_seen_at,
+);
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSessionDTO {
+    pub user_id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -46,6 +189,7 @@ pub struct NewFollowsDTO {
 
 
 
+#[tracing::instrument(skip_all, fields(username = %new_user.username))]
 pub fn create(pool: &DbPool, new_user: NewUserDTO) -> QueryResult<UserEntity> {
     use diesel::insert_into;
     let conn = pool.get().unwrap();
@@ -54,13 +198,15 @@ pub fn create(pool: &DbPool, new_user: NewUserDTO) -> QueryResult<UserEntity> {
         .get_result::<UserEntity>(&conn)
 }
 
+#[tracing::instrument(skip_all, fields(username = %given_username))]
 pub fn get_user_by_username(pool: &DbPool, given_username: &String) -> QueryResult<UserEntity> {
     let conn = pool.get().unwrap();
     users
-    .filter(username.eq(given_username))
+    .filter(lower(username).eq(lower(given_username)))
     .first::<UserEntity>(&conn)
 }
 
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
 pub fn get_user_by_id(pool: &DbPool, given_id: &i32) -> QueryResult<UserEntity> {
     let conn = pool.get().unwrap();
     users
@@ -68,48 +214,31 @@ pub fn get_user_by_id(pool: &DbPool, given_id: &i32) -> QueryResult<UserEntity>
     .first::<UserEntity>(&conn)
 }
 
+#[tracing::instrument(skip_all)]
+pub fn get_user_by_email(pool: &DbPool, given_email: &str) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    users
+    .filter(lower(email).eq(lower(given_email)))
+    .first::<UserEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
 pub fn update_user(pool: &DbPool, user_update_dto: UserUpdateDTO, given_id: &i32) -> QueryResult<UserEntity> {             
     let conn = pool.get().unwrap();
     diesel::update(users.filter(id.eq(given_id)))
     .set(user_update_dto).get_result::<UserEntity>(&conn)
 }
 
-pub fn get_follows(pool: &DbPool, given_follower_id: &i32, given_followed_username: &String) -> QueryResult<bool> {
+#[tracing::instrument(skip_all, fields(follower_id = given_follower_id, followed_username = %given_followed_username))]
+pub fn follow(pool: &DbPool, given_follower_id: &i32, given_followed_username: &String) -> QueryResult<bool> {
     let conn = pool.get().unwrap();
     let given_followed_id = users
-    .filter(username.eq(given_followed_username))
+    .filter(lower(username).eq(lower(given_followed_username)))
     .select(id)
     .first::<i32>(&conn)?;
-    let follows_active = follows.filter(
-        follower_id.eq(given_follower_id)
-        .and(followed_id.eq(given_followed_id))
-    ).select(active)
-    .first::<bool>(&conn); 
-    match follows_active {
-        Err(diesel::result::Error::NotFound) => {
-                use diesel::insert_into;
-                let exec_result = insert_into(follows)
-                .values(&NewFollowsDTO{
-                    followed_id: given_followed_id,
-                    follower_id: given_follower_id.to_owned(),
-                    active: false
-                }).execute(&conn);
-                if let Err(e) = exec_result  {
-                    return Err(e);
-                }
-                Ok(false)
-        },
-        Ok(x) => Ok(x),
-        Err(y) => Err(y)
+    if is_blocked_between(&conn, *given_follower_id, given_followed_id)? {
+        return Ok(false);
     }
-}
-
-pub fn follow(pool: &DbPool, given_follower_id: &i32, given_followed_username: &String) -> QueryResult<()> {
-    let conn = pool.get().unwrap();
-    let given_followed_id = users
-    .filter(username.eq(given_followed_username))
-    .select(id)
-    .first::<i32>(&conn)?;
                 use diesel::insert_into;
     insert_into(follows)
       .values(&NewFollowsDTO {
@@ -121,13 +250,14 @@ pub fn follow(pool: &DbPool, given_follower_id: &i32, given_followed_username: &
       ).do_update()
       .set(active.eq(true))
       .execute(&conn)
-      .map(|_| ())
+      .map(|_| true)
 }
 
+#[tracing::instrument(skip_all, fields(follower_id = given_follower_id, followed_username = %given_followed_username))]
 pub fn unfollow(pool: &DbPool, given_follower_id: &i32, given_followed_username: &String) -> QueryResult<()> {
     let conn = pool.get().unwrap();
     let given_followed_id = users
-    .filter(username.eq(given_followed_username))
+    .filter(lower(username).eq(lower(given_followed_username)))
     .select(id)
     .first::<i32>(&conn)?;
                 use diesel::insert_into;
@@ -144,3 +274,922 @@ pub fn unfollow(pool: &DbPool, given_follower_id: &i32, given_followed_username:
       .map(|_| ())
 }
 
+
+/// Sets a new password and, as for a reset by email, revokes every session.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn update_password(pool: &DbPool, given_id: &i32, given_password_hash: String) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        revoke_all_sessions(&conn, *given_id, Utc::now())?;
+        diesel::update(users.filter(id.eq(given_id)))
+        .set(password_hash.eq(given_password_hash))
+        .get_result::<UserEntity>(&conn)
+    })
+}
+
+/// Revokes every session of a user, which also stops the JWTs and personal
+/// access tokens issued before `now`. Part of any password reset.
+fn revoke_all_sessions(conn: &PgConnection, given_id: i32, now: DateTime<Utc>) -> QueryResult<()> {
+    diesel::update(
+        sessions::table
+        .filter(sessions::user_id.eq(given_id))
+        .filter(sessions::revoked_at.is_null())
+    )
+    .set(sessions::revoked_at.eq(now))
+    .execute(conn)?;
+    diesel::update(users.filter(id.eq(given_id)))
+    .set(sessions_revoked_at.eq(now))
+    .execute(conn)?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(user_id = given_id, disabled = disabled))]
+pub fn set_disabled(pool: &DbPool, given_id: &i32, disabled: bool) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    let given_disabled_at = if disabled { Some(Utc::now()) } else { None };
+    diesel::update(users.filter(id.eq(given_id)))
+    .set(disabled_at.eq(given_disabled_at))
+    .get_result::<UserEntity>(&conn)
+}
+
+/// What happens to a user's content when their account is deleted, picked
+/// with `ACCOUNT_DELETION_POLICY`.
+#[derive(Clone, Copy, Debug)]
+pub enum DeletionPolicy {
+    /// Remove the user row; articles, favorites and follows cascade with it.
+    Delete,
+    /// Scrub the user row and keep their articles under an anonymous author.
+    /// Favorites and follows are removed.
+    Anonymize,
+}
+
+impl DeletionPolicy {
+    pub fn from_env() -> Self {
+        match std::env::var("ACCOUNT_DELETION_POLICY").as_deref() {
+            Ok("anonymize") => DeletionPolicy::Anonymize,
+            _ => DeletionPolicy::Delete,
+        }
+    }
+}
+
+/// Deletes a user and everything that references them.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn delete_user(pool: &DbPool, given_id: &i32) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::delete(users.filter(id.eq(given_id))).execute(&conn)?;
+    Ok(())
+}
+
+/// Removes everything personal about a user while keeping the row, so their
+/// articles stay readable under a placeholder name. The account can no longer
+/// be logged into.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn anonymize_user(pool: &DbPool, given_id: &i32) -> QueryResult<()> {
+    use crate::db_schema::user_favorites_article;
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        diesel::delete(follows.filter(follower_id.eq(given_id).or(followed_id.eq(given_id))))
+        .execute(&conn)?;
+        diesel::delete(user_favorites_article::table.filter(user_favorites_article::user_id.eq(given_id)))
+        .execute(&conn)?;
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(given_id)))
+        .execute(&conn)?;
+        diesel::delete(
+            user_restrictions::table
+            .filter(user_restrictions::user_id.eq(given_id).or(user_restrictions::target_id.eq(given_id)))
+        )
+        .execute(&conn)?;
+        let placeholder = placeholder_username(&conn, *given_id)?;
+        diesel::update(users.filter(id.eq(given_id)))
+        .set((
+            email.eq(format!("{}@invalid", placeholder)),
+            username.eq(placeholder),
+            bio.eq(None::<String>),
+            image.eq(None::<String>),
+            password_hash.eq(""),
+            disabled_at.eq(Some(Utc::now())),
+            pending_email.eq(None::<String>),
+            totp_secret.eq(None::<String>),
+            totp_enabled_at.eq(None::<DateTime<Utc>>),
+        ))
+        .execute(&conn)?;
+        Ok(())
+    })
+}
+
+/// `deleted-user-{id}`, or with a `-2`, `-3`... suffix if someone registered
+/// that username or the matching email, which nothing keeps them from doing.
+fn placeholder_username(conn: &PgConnection, given_id: i32) -> QueryResult<String> {
+    for n in 1.. {
+        let candidate = match n {
+            1 => format!("deleted-user-{}", given_id),
+            n => format!("deleted-user-{}-{}", given_id, n),
+        };
+        let taken = users
+        .filter(id.ne(given_id))
+        .filter(
+            lower(username).eq(candidate.to_lowercase())
+            .or(lower(email).eq(format!("{}@invalid", candidate)))
+        )
+        .select(id)
+        .first::<i32>(conn)
+        .optional()?;
+        if taken.is_none() {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+/// Usernames the user actively follows.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_following_usernames(pool: &DbPool, given_id: &i32) -> QueryResult<Vec<String>> {
+    let conn = pool.get().unwrap();
+    follows
+    .inner_join(users.on(id.eq(followed_id)))
+    .filter(follower_id.eq(given_id).and(active.eq(true)))
+    .select(username)
+    .load::<String>(&conn)
+}
+
+/// Usernames actively following the user.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_follower_usernames(pool: &DbPool, given_id: &i32) -> QueryResult<Vec<String>> {
+    let conn = pool.get().unwrap();
+    follows
+    .inner_join(users.on(id.eq(follower_id)))
+    .filter(followed_id.eq(given_id).and(active.eq(true)))
+    .select(username)
+    .load::<String>(&conn)
+}
+
+/// Active followers of each of the users, as `(user_id, count)`. Users
+/// without followers are left out.
+#[tracing::instrument(skip_all, fields(users = given_ids.len()))]
+pub fn count_followers(pool: &DbPool, given_ids: &[i32]) -> QueryResult<Vec<(i32, i64)>> {
+    let conn = pool.get().unwrap();
+    follows
+    .filter(followed_id.eq_any(given_ids).and(active.eq(true)))
+    .group_by(followed_id)
+    .select((followed_id, diesel::dsl::sql::<diesel::sql_types::BigInt>("COUNT(*)")))
+    .load::<(i32, i64)>(&conn)
+}
+
+/// How many users each of the users actively follows, as `(user_id, count)`.
+#[tracing::instrument(skip_all, fields(users = given_ids.len()))]
+pub fn count_following(pool: &DbPool, given_ids: &[i32]) -> QueryResult<Vec<(i32, i64)>> {
+    let conn = pool.get().unwrap();
+    follows
+    .filter(follower_id.eq_any(given_ids).and(active.eq(true)))
+    .group_by(follower_id)
+    .select((follower_id, diesel::dsl::sql::<diesel::sql_types::BigInt>("COUNT(*)")))
+    .load::<(i32, i64)>(&conn)
+}
+
+/// Which of the users `given_follower_id` actively follows.
+#[tracing::instrument(skip_all, fields(follower_id = given_follower_id, users = given_ids.len()))]
+pub fn get_followed_among(pool: &DbPool, given_follower_id: &i32, given_ids: &[i32]) -> QueryResult<Vec<i32>> {
+    let conn = pool.get().unwrap();
+    follows
+    .filter(follower_id.eq(given_follower_id))
+    .filter(followed_id.eq_any(given_ids).and(active.eq(true)))
+    .select(followed_id)
+    .load::<i32>(&conn)
+}
+
+/// Which of the users actively follow `given_followed_id`.
+#[tracing::instrument(skip_all, fields(followed_id = given_followed_id, users = given_ids.len()))]
+pub fn get_followers_among(pool: &DbPool, given_followed_id: &i32, given_ids: &[i32]) -> QueryResult<Vec<i32>> {
+    let conn = pool.get().unwrap();
+    follows
+    .filter(followed_id.eq(given_followed_id))
+    .filter(follower_id.eq_any(given_ids).and(active.eq(true)))
+    .select(follower_id)
+    .load::<i32>(&conn)
+}
+
+/// Up to `limit` active followers of the user with an id above `after`, by id.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_followers_page(pool: &DbPool, given_id: &i32, after: Option<i32>, limit: i64) -> QueryResult<Vec<UserEntity>> {
+    let conn = pool.get().unwrap();
+    follows
+    .inner_join(users.on(id.eq(follower_id)))
+    .filter(followed_id.eq(given_id).and(active.eq(true)))
+    .filter(id.gt(after.unwrap_or(0)))
+    .order(id.asc())
+    .limit(limit)
+    .select(users::all_columns)
+    .load::<UserEntity>(&conn)
+}
+
+/// Up to `limit` users actively followed by the user with an id above `after`, by id.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_followed_page(pool: &DbPool, given_id: &i32, after: Option<i32>, limit: i64) -> QueryResult<Vec<UserEntity>> {
+    let conn = pool.get().unwrap();
+    follows
+    .inner_join(users.on(id.eq(followed_id)))
+    .filter(follower_id.eq(given_id).and(active.eq(true)))
+    .filter(id.gt(after.unwrap_or(0)))
+    .order(id.asc())
+    .limit(limit)
+    .select(users::all_columns)
+    .load::<UserEntity>(&conn)
+}
+
+/// Up to `limit` enabled users whose username or public bio resembles the
+/// query (trigram matching), most similar first and then most followed,
+/// skipping the first `offset`.
+#[tracing::instrument(skip_all, fields(query = %given_query))]
+pub fn search_users(pool: &DbPool, given_query: &str, offset: i64, limit: i64) -> QueryResult<Vec<UserEntity>> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{BigInt, Bool, Float, Text};
+    let conn = pool.get().unwrap();
+    users
+    .filter(disabled_at.is_null())
+    .filter(
+        sql::<Bool>("(username % ")
+        .bind::<Text, _>(given_query)
+        .sql(" OR (profile_visibility = 'public' AND ")
+        .bind::<Text, _>(given_query)
+        .sql(" <% bio))")
+    )
+    .order((
+        sql::<Float>("GREATEST(similarity(username, ")
+        .bind::<Text, _>(given_query)
+        .sql("), word_similarity(")
+        .bind::<Text, _>(given_query)
+        .sql(", CASE WHEN profile_visibility = 'public' THEN COALESCE(bio, '') ELSE '' END))")
+        .desc(),
+        sql::<BigInt>("(SELECT COUNT(*) FROM follows WHERE follows.followed_id = users.id AND follows.active)").desc(),
+        id.asc(),
+    ))
+    .offset(offset)
+    .limit(limit)
+    .load::<UserEntity>(&conn)
+}
+
+/// Up to `limit` authors the user does not follow yet, ranked by how many of
+/// the users they follow follow them. Authors hidden from the user (see
+/// `get_hidden_author_ids`) are left out.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_suggested_authors(pool: &DbPool, given_id: &i32, limit: i64) -> QueryResult<Vec<UserEntity>> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{BigInt, Bool, Integer};
+    let hidden_ids = get_hidden_author_ids(pool, given_id)?;
+    let conn = pool.get().unwrap();
+    let followers_among_followed = || {
+        sql::<BigInt>(
+            "(SELECT COUNT(*) FROM follows AS followed \
+             JOIN follows AS their ON their.follower_id = followed.followed_id \
+             WHERE followed.follower_id = "
+        )
+        .bind::<Integer, _>(*given_id)
+        .sql(" AND followed.active AND their.active AND their.followed_id = users.id)")
+    };
+    users
+    .filter(disabled_at.is_null())
+    .filter(id.ne(given_id))
+    .filter(id.ne_all(hidden_ids))
+    .filter(sql::<Bool>("EXISTS (SELECT 1 FROM articles WHERE articles.author_id = users.id AND articles.status = 'published')"))
+    .filter(
+        sql::<Bool>("NOT EXISTS (SELECT 1 FROM follows WHERE follows.followed_id = users.id AND follows.active AND follows.follower_id = ")
+        .bind::<Integer, _>(*given_id)
+        .sql(")")
+    )
+    .filter(followers_among_followed().gt(0))
+    .order((followers_among_followed().desc(), id.asc()))
+    .limit(limit)
+    .load::<UserEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn count(pool: &DbPool) -> QueryResult<i64> {
+    let conn = pool.get().unwrap();
+    users.count().get_result::<i64>(&conn)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn count_active_follows(pool: &DbPool) -> QueryResult<i64> {
+    let conn = pool.get().unwrap();
+    follows.filter(active.eq(true)).count().get_result::<i64>(&conn)
+}
+
+/// Changes a user's role and records who did it. `given_actor_id` is `None`
+/// when the change comes from the command line.
+#[tracing::instrument(skip_all, fields(actor_id = ?given_actor_id, user_id = given_id, role = %given_role))]
+pub fn set_role(pool: &DbPool, given_actor_id: Option<i32>, given_id: &i32, given_role: &str) -> QueryResult<RoleChangeEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        let previous_role = users
+        .filter(id.eq(given_id))
+        .select(role)
+        .for_update()
+        .first::<String>(&conn)?;
+        diesel::update(users.filter(id.eq(given_id)))
+        .set(role.eq(given_role))
+        .execute(&conn)?;
+        diesel::insert_into(role_changes::table)
+        .values(&NewRoleChangeDTO {
+            actor_id: given_actor_id,
+            target_id: Some(*given_id),
+            old_role: previous_role,
+            new_role: given_role.to_string(),
+        })
+        .get_result::<RoleChangeEntity>(&conn)
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn get_role_changes(pool: &DbPool, limit: i64, offset: i64) -> QueryResult<Vec<RoleChangeEntity>> {
+    let conn = pool.get().unwrap();
+    role_changes::table
+    .order_by(role_changes::changed_at.desc())
+    .limit(limit)
+    .offset(offset)
+    .load::<RoleChangeEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = new_token.user_id))]
+pub fn create_password_reset_token(pool: &DbPool, new_token: NewPasswordResetTokenDTO) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(password_reset_tokens::table)
+    .values(&new_token)
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// Redeems an unused, unexpired reset token: sets the new password, burns
+/// every outstanding token of the user and revokes the tokens issued before
+/// now. Returns `None` when the token cannot be used.
+#[tracing::instrument(skip_all)]
+pub fn reset_password(pool: &DbPool, given_token_hash: &str, given_password_hash: String) -> QueryResult<Option<UserEntity>> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        let now = Utc::now();
+        let token_user_id = password_reset_tokens::table
+        .filter(password_reset_tokens::token_hash.eq(given_token_hash))
+        .filter(password_reset_tokens::used_at.is_null())
+        .filter(password_reset_tokens::expires_at.gt(now))
+        .select(password_reset_tokens::user_id)
+        .for_update()
+        .first::<i32>(&conn)
+        .optional()?;
+        let token_user_id = match token_user_id {
+            Some(token_user_id) => token_user_id,
+            None => return Ok(None),
+        };
+        diesel::update(
+            password_reset_tokens::table
+            .filter(password_reset_tokens::user_id.eq(token_user_id))
+            .filter(password_reset_tokens::used_at.is_null())
+        )
+        .set(password_reset_tokens::used_at.eq(now))
+        .execute(&conn)?;
+        revoke_all_sessions(&conn, token_user_id, now)?;
+        diesel::update(users.filter(id.eq(token_user_id)))
+        .set(password_hash.eq(given_password_hash))
+        .get_result::<UserEntity>(&conn)
+        .map(Some)
+    })
+}
+
+/// Records the address a user wants to switch to, or clears it with `None`.
+/// The email itself only changes once the new address is verified.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn set_pending_email(pool: &DbPool, given_id: &i32, given_email: Option<String>) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    diesel::update(users.filter(id.eq(given_id)))
+    .set(pending_email.eq(given_email))
+    .get_result::<UserEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn set_image(pool: &DbPool, given_id: &i32, given_image: Option<String>) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    diesel::update(users.filter(id.eq(given_id)))
+    .set(image.eq(given_image))
+    .get_result::<UserEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = new_token.user_id))]
+pub fn create_email_verification_token(pool: &DbPool, new_token: NewEmailVerificationTokenDTO) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(email_verification_tokens::table)
+    .values(&new_token)
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// Redeems an unused, unexpired verification token. The address it was sent
+/// to becomes the user's verified email if it is still their current or
+/// pending one; otherwise, or when the token cannot be used, returns `None`.
+#[tracing::instrument(skip_all)]
+pub fn verify_email(pool: &DbPool, given_token_hash: &str) -> QueryResult<Option<UserEntity>> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        let now = Utc::now();
+        let token = email_verification_tokens::table
+        .filter(email_verification_tokens::token_hash.eq(given_token_hash))
+        .filter(email_verification_tokens::used_at.is_null())
+        .filter(email_verification_tokens::expires_at.gt(now))
+        .select((email_verification_tokens::id, email_verification_tokens::user_id, email_verification_tokens::email))
+        .for_update()
+        .first::<(i32, i32, String)>(&conn)
+        .optional()?;
+        let (token_id, token_user_id, token_email) = match token {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        diesel::update(email_verification_tokens::table.filter(email_verification_tokens::id.eq(token_id)))
+        .set(email_verification_tokens::used_at.eq(now))
+        .execute(&conn)?;
+        let user = users
+        .filter(id.eq(token_user_id))
+        .for_update()
+        .first::<UserEntity>(&conn)?;
+        if user.pending_email.as_deref() == Some(token_email.as_str()) {
+            diesel::update(users.filter(id.eq(token_user_id)))
+            .set((
+                email.eq(token_email),
+                pending_email.eq(None::<String>),
+                email_verified_at.eq(now),
+            ))
+            .get_result::<UserEntity>(&conn)
+            .map(Some)
+        } else if user.email == token_email {
+            diesel::update(users.filter(id.eq(token_user_id)))
+            .set(email_verified_at.eq(now))
+            .get_result::<UserEntity>(&conn)
+            .map(Some)
+        } else {
+            Ok(None)
+        }
+    })
+}
+
+/// When repeated login failures lock an account, read from
+/// `LOGIN_LOCKOUT_THRESHOLD`, `LOGIN_LOCKOUT_SECONDS` and
+/// `LOGIN_LOCKOUT_MAX_SECONDS`.
+#[derive(Clone, Copy, Debug)]
+pub struct LockoutPolicy {
+    /// Consecutive failures before the first lockout.
+    pub threshold: i32,
+    /// Length of the first lockout, doubled on every further failure.
+    pub base: chrono::Duration,
+    pub max: chrono::Duration,
+}
+
+impl LockoutPolicy {
+    pub fn from_env() -> Self {
+        let read = |var: &str, default: i64| {
+            std::env::var(var)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        LockoutPolicy {
+            threshold: read("LOGIN_LOCKOUT_THRESHOLD", 5) as i32,
+            base: chrono::Duration::seconds(read("LOGIN_LOCKOUT_SECONDS", 60)),
+            max: chrono::Duration::seconds(read("LOGIN_LOCKOUT_MAX_SECONDS", 24 * 60 * 60)),
+        }
+    }
+
+    /// How long to lock an account after `failures` consecutive failures.
+    pub fn lock_duration(&self, failures: i32) -> Option<chrono::Duration> {
+        if failures < self.threshold {
+            return None;
+        }
+        let doublings = (failures - self.threshold).min(20) as u32;
+        Some(std::cmp::min(self.base * 2i32.pow(doublings), self.max))
+    }
+}
+
+/// Counts a failed login and locks the account once the policy says so.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn record_failed_login(pool: &DbPool, given_id: &i32, policy: LockoutPolicy) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        let failures = users
+        .filter(id.eq(given_id))
+        .select(failed_login_attempts)
+        .for_update()
+        .first::<i32>(&conn)? + 1;
+        let given_locked_until = policy.lock_duration(failures).map(|duration| Utc::now() + duration);
+        diesel::update(users.filter(id.eq(given_id)))
+        .set((
+            failed_login_attempts.eq(failures),
+            locked_until.eq(given_locked_until),
+        ))
+        .get_result::<UserEntity>(&conn)
+    })
+}
+
+/// Clears failed logins and any lockout, after a successful login or by an admin.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn reset_failed_logins(pool: &DbPool, given_id: &i32) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    diesel::update(users.filter(id.eq(given_id)))
+    .set((
+        failed_login_attempts.eq(0),
+        locked_until.eq(None::<DateTime<Utc>>),
+    ))
+    .get_result::<UserEntity>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = ?attempt.user_id, succeeded = attempt.succeeded))]
+pub fn record_login_attempt(pool: &DbPool, attempt: NewLoginAttemptDTO) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(login_attempts::table)
+    .values(&attempt)
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// Stores a new TOTP secret that only takes effect once confirmed, or clears
+/// a pending one with `None`.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn set_totp_secret(pool: &DbPool, given_id: &i32, given_secret: Option<String>) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    diesel::update(users.filter(id.eq(given_id)))
+    .set((
+        totp_secret.eq(given_secret),
+        totp_enabled_at.eq(None::<DateTime<Utc>>),
+        totp_last_used_step.eq(None::<i64>),
+    ))
+    .get_result::<UserEntity>(&conn)
+}
+
+/// Turns TOTP on once the first code has been checked, replacing any
+/// previous recovery codes with `recovery_code_hashes`.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn enable_totp(pool: &DbPool, given_id: &i32, given_step: i64, recovery_code_hashes: Vec<String>) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(given_id)))
+        .execute(&conn)?;
+        let rows = recovery_code_hashes
+        .into_iter()
+        .map(|hash| (mfa_recovery_codes::user_id.eq(*given_id), mfa_recovery_codes::code_hash.eq(hash)))
+        .collect::<Vec<_>>();
+        diesel::insert_into(mfa_recovery_codes::table)
+        .values(&rows)
+        .execute(&conn)?;
+        diesel::update(users.filter(id.eq(given_id)))
+        .set((
+            totp_enabled_at.eq(Utc::now()),
+            totp_last_used_step.eq(given_step),
+        ))
+        .get_result::<UserEntity>(&conn)
+    })
+}
+
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn disable_totp(pool: &DbPool, given_id: &i32) -> QueryResult<UserEntity> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(given_id)))
+        .execute(&conn)?;
+        diesel::delete(mfa_challenges::table.filter(mfa_challenges::user_id.eq(given_id)))
+        .execute(&conn)?;
+        diesel::update(users.filter(id.eq(given_id)))
+        .set((
+            totp_secret.eq(None::<String>),
+            totp_enabled_at.eq(None::<DateTime<Utc>>),
+            totp_last_used_step.eq(None::<i64>),
+        ))
+        .get_result::<UserEntity>(&conn)
+    })
+}
+
+/// Remembers the last accepted TOTP step so its code cannot be used again.
+/// Returns false if a later step was recorded in the meantime.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn use_totp_step(pool: &DbPool, given_id: &i32, given_step: i64) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    diesel::update(
+        users
+        .filter(id.eq(given_id))
+        .filter(totp_last_used_step.is_null().or(totp_last_used_step.lt(given_step)))
+    )
+    .set(totp_last_used_step.eq(given_step))
+    .execute(&conn)
+    .map(|updated| updated == 1)
+}
+
+/// Burns a recovery code; false if it does not exist or was already used.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn use_recovery_code(pool: &DbPool, given_id: &i32, given_code_hash: &str) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    diesel::update(
+        mfa_recovery_codes::table
+        .filter(mfa_recovery_codes::user_id.eq(given_id))
+        .filter(mfa_recovery_codes::code_hash.eq(given_code_hash))
+        .filter(mfa_recovery_codes::used_at.is_null())
+    )
+    .set(mfa_recovery_codes::used_at.eq(Utc::now()))
+    .execute(&conn)
+    .map(|updated| updated == 1)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = new_challenge.user_id))]
+pub fn create_mfa_challenge(pool: &DbPool, new_challenge: NewMfaChallengeDTO) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(mfa_challenges::table)
+    .values(&new_challenge)
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// The `(id, user_id)` of an unused, unexpired challenge that has had fewer
+/// than `max_failures` wrong codes.
+#[tracing::instrument(skip_all)]
+pub fn get_mfa_challenge(pool: &DbPool, given_challenge_hash: &str, max_failures: i32) -> QueryResult<Option<(i32, i32)>> {
+    let conn = pool.get().unwrap();
+    mfa_challenges::table
+    .filter(mfa_challenges::challenge_hash.eq(given_challenge_hash))
+    .filter(mfa_challenges::used_at.is_null())
+    .filter(mfa_challenges::expires_at.gt(Utc::now()))
+    .filter(mfa_challenges::failed_attempts.lt(max_failures))
+    .select((mfa_challenges::id, mfa_challenges::user_id))
+    .first::<(i32, i32)>(&conn)
+    .optional()
+}
+
+#[tracing::instrument(skip_all, fields(challenge_id = given_challenge_id))]
+pub fn fail_mfa_challenge(pool: &DbPool, given_challenge_id: i32) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::update(mfa_challenges::table.filter(mfa_challenges::id.eq(given_challenge_id)))
+    .set(mfa_challenges::failed_attempts.eq(mfa_challenges::failed_attempts + 1))
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// Marks a challenge as used; false if it was used concurrently.
+#[tracing::instrument(skip_all, fields(challenge_id = given_challenge_id))]
+pub fn use_mfa_challenge(pool: &DbPool, given_challenge_id: i32) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    diesel::update(
+        mfa_challenges::table
+        .filter(mfa_challenges::id.eq(given_challenge_id))
+        .filter(mfa_challenges::used_at.is_null())
+    )
+    .set(mfa_challenges::used_at.eq(Utc::now()))
+    .execute(&conn)
+    .map(|updated| updated == 1)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = new_token.user_id))]
+pub fn create_personal_access_token(pool: &DbPool, new_token: NewPersonalAccessTokenDTO) -> QueryResult<PersonalAccessTokenEntity> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(personal_access_tokens::table)
+    .values(&new_token)
+    .returning(PERSONAL_ACCESS_TOKEN_COLUMNS)
+    .get_result::<PersonalAccessTokenEntity>(&conn)
+}
+
+/// The tokens of a user that have not been revoked, newest first. Expired
+/// ones are kept so their owner can see why a script stopped working.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_personal_access_tokens(pool: &DbPool, given_id: &i32) -> QueryResult<Vec<PersonalAccessTokenEntity>> {
+    let conn = pool.get().unwrap();
+    personal_access_tokens::table
+    .filter(personal_access_tokens::user_id.eq(given_id))
+    .filter(personal_access_tokens::revoked_at.is_null())
+    .order(personal_access_tokens::created_at.desc())
+    .select(PERSONAL_ACCESS_TOKEN_COLUMNS)
+    .load::<PersonalAccessTokenEntity>(&conn)
+}
+
+/// An unrevoked, unexpired token and the user it belongs to.
+#[tracing::instrument(skip_all)]
+pub fn get_active_personal_access_token(pool: &DbPool, given_token_hash: &str) -> QueryResult<Option<(PersonalAccessTokenEntity, UserEntity)>> {
+    let conn = pool.get().unwrap();
+    personal_access_tokens::table
+    .inner_join(users::table)
+    .filter(personal_access_tokens::token_hash.eq(given_token_hash))
+    .filter(personal_access_tokens::revoked_at.is_null())
+    .filter(
+        personal_access_tokens::expires_at.is_null()
+        .or(personal_access_tokens::expires_at.gt(Utc::now()))
+    )
+    .select((PERSONAL_ACCESS_TOKEN_COLUMNS, users::all_columns))
+    .first::<(PersonalAccessTokenEntity, UserEntity)>(&conn)
+    .optional()
+}
+
+/// How often at most the use of a token is recorded, so busy bots do not
+/// write on every request.
+pub const TOKEN_TOUCH_INTERVAL_MINUTES: i64 = 1;
+
+/// Records that a token was used, unless that was recorded less than
+/// `TOKEN_TOUCH_INTERVAL_MINUTES` ago.
+#[tracing::instrument(skip_all, fields(token_id = given_token_id))]
+pub fn touch_personal_access_token(pool: &DbPool, given_token_id: i32) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    let now = Utc::now();
+    diesel::update(
+        personal_access_tokens::table
+        .filter(personal_access_tokens::id.eq(given_token_id))
+        .filter(
+            personal_access_tokens::last_used_at.is_null()
+            .or(personal_access_tokens::last_used_at.lt(now - chrono::Duration::minutes(TOKEN_TOUCH_INTERVAL_MINUTES)))
+        )
+    )
+    .set(personal_access_tokens::last_used_at.eq(now))
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// Revokes one of the user's tokens; false if they have no such token.
+#[tracing::instrument(skip_all, fields(user_id = given_id, token_id = given_token_id))]
+pub fn revoke_personal_access_token(pool: &DbPool, given_id: &i32, given_token_id: i32) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    diesel::update(
+        personal_access_tokens::table
+        .filter(personal_access_tokens::id.eq(given_token_id))
+        .filter(personal_access_tokens::user_id.eq(given_id))
+        .filter(personal_access_tokens::revoked_at.is_null())
+    )
+    .set(personal_access_tokens::revoked_at.eq(Utc::now()))
+    .execute(&conn)
+    .map(|updated| updated == 1)
+}
+
+#[tracing::instrument(skip_all, fields(user_id = new_session.user_id))]
+pub fn create_session(pool: &DbPool, new_session: NewSessionDTO) -> QueryResult<SessionEntity> {
+    let conn = pool.get().unwrap();
+    diesel::insert_into(sessions::table)
+    .values(&new_session)
+    .returning(SESSION_COLUMNS)
+    .get_result::<SessionEntity>(&conn)
+}
+
+/// The user of a session, if it has not been revoked.
+#[tracing::instrument(skip_all, fields(session_id = given_session_id))]
+pub fn get_live_session_user(pool: &DbPool, given_session_id: i32) -> QueryResult<Option<UserEntity>> {
+    let conn = pool.get().unwrap();
+    sessions::table
+    .inner_join(users::table)
+    .filter(sessions::id.eq(given_session_id))
+    .filter(sessions::revoked_at.is_null())
+    .select(users::all_columns)
+    .first::<UserEntity>(&conn)
+    .optional()
+}
+
+#[tracing::instrument(skip_all, fields(session_id = given_session_id))]
+pub fn get_session_created_at(pool: &DbPool, given_session_id: i32) -> QueryResult<DateTime<Utc>> {
+    let conn = pool.get().unwrap();
+    sessions::table
+    .filter(sessions::id.eq(given_session_id))
+    .select(sessions::created_at)
+    .first::<DateTime<Utc>>(&conn)
+}
+
+#[tracing::instrument(skip_all, fields(session_id = given_session_id))]
+pub fn touch_session(pool: &DbPool, given_session_id: i32) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::update(sessions::table.filter(sessions::id.eq(given_session_id)))
+    .set(sessions::last_seen_at.eq(Utc::now()))
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// The unrevoked sessions of a user seen since `seen_since`, most recently
+/// used first.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_sessions(pool: &DbPool, given_id: &i32, seen_since: DateTime<Utc>) -> QueryResult<Vec<SessionEntity>> {
+    let conn = pool.get().unwrap();
+    sessions::table
+    .filter(sessions::user_id.eq(given_id))
+    .filter(sessions::revoked_at.is_null())
+    .filter(sessions::last_seen_at.gt(seen_since))
+    .order(sessions::last_seen_at.desc())
+    .select(SESSION_COLUMNS)
+    .load::<SessionEntity>(&conn)
+}
+
+/// Revokes one of the user's sessions; false if they have no such live session.
+#[tracing::instrument(skip_all, fields(user_id = given_id, session_id = given_session_id))]
+pub fn revoke_session(pool: &DbPool, given_id: &i32, given_session_id: i32) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    diesel::update(
+        sessions::table
+        .filter(sessions::id.eq(given_session_id))
+        .filter(sessions::user_id.eq(given_id))
+        .filter(sessions::revoked_at.is_null())
+    )
+    .set(sessions::revoked_at.eq(Utc::now()))
+    .execute(&conn)
+    .map(|updated| updated == 1)
+}
+
+/// A block hides the blocker's articles from the blocked user and keeps the
+/// two from following each other; it also mutes the blocked user for the
+/// blocker. A mute only keeps the muted user's articles out of the muter's
+/// lists.
+pub const BLOCK: &str = "block";
+pub const MUTE: &str = "mute";
+
+#[derive(Insertable)]
+#[table_name = "user_restrictions"]
+struct NewRestrictionDTO<'a> {
+    user_id: i32,
+    target_id: i32,
+    kind: &'a str,
+}
+
+/// Whether either user has blocked the other.
+fn is_blocked_between(conn: &PgConnection, given_id: i32, given_other_id: i32) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        user_restrictions::table
+        .filter(user_restrictions::kind.eq(BLOCK))
+        .filter(
+            user_restrictions::user_id.eq(given_id).and(user_restrictions::target_id.eq(given_other_id))
+            .or(user_restrictions::user_id.eq(given_other_id).and(user_restrictions::target_id.eq(given_id)))
+        )
+    ))
+    .get_result::<bool>(conn)
+}
+
+/// Blocks or mutes a user; blocking also ends the follows between the two.
+#[tracing::instrument(skip_all, fields(user_id = given_id, target_id = given_target_id, kind = given_kind))]
+pub fn restrict(pool: &DbPool, given_id: &i32, given_target_id: &i32, given_kind: &str) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    conn.transaction(|| {
+        diesel::insert_into(user_restrictions::table)
+        .values(&NewRestrictionDTO {
+            user_id: *given_id,
+            target_id: *given_target_id,
+            kind: given_kind,
+        })
+        .on_conflict_do_nothing()
+        .execute(&conn)?;
+        if given_kind == BLOCK {
+            diesel::delete(
+                follows.filter(
+                    follower_id.eq(given_id).and(followed_id.eq(given_target_id))
+                    .or(follower_id.eq(given_target_id).and(followed_id.eq(given_id)))
+                )
+            )
+            .execute(&conn)?;
+        }
+        Ok(())
+    })
+}
+
+#[tracing::instrument(skip_all, fields(user_id = given_id, target_id = given_target_id, kind = given_kind))]
+pub fn unrestrict(pool: &DbPool, given_id: &i32, given_target_id: &i32, given_kind: &str) -> QueryResult<()> {
+    let conn = pool.get().unwrap();
+    diesel::delete(
+        user_restrictions::table
+        .filter(user_restrictions::user_id.eq(given_id))
+        .filter(user_restrictions::target_id.eq(given_target_id))
+        .filter(user_restrictions::kind.eq(given_kind))
+    )
+    .execute(&conn)
+    .map(|_| ())
+}
+
+/// Whether `given_blocker_id` has blocked `given_id`.
+#[tracing::instrument(skip_all, fields(blocker_id = given_blocker_id, user_id = given_id))]
+pub fn has_blocked(pool: &DbPool, given_blocker_id: &i32, given_id: &i32) -> QueryResult<bool> {
+    let conn = pool.get().unwrap();
+    diesel::select(diesel::dsl::exists(
+        user_restrictions::table
+        .filter(user_restrictions::user_id.eq(given_blocker_id))
+        .filter(user_restrictions::target_id.eq(given_id))
+        .filter(user_restrictions::kind.eq(BLOCK))
+    ))
+    .get_result::<bool>(&conn)
+}
+
+/// How the user restricts each of the users, as `(target_id, kind)`.
+#[tracing::instrument(skip_all, fields(user_id = given_id, users = given_ids.len()))]
+pub fn get_restrictions_among(pool: &DbPool, given_id: &i32, given_ids: &[i32]) -> QueryResult<Vec<(i32, String)>> {
+    let conn = pool.get().unwrap();
+    user_restrictions::table
+    .filter(user_restrictions::user_id.eq(given_id))
+    .filter(user_restrictions::target_id.eq_any(given_ids))
+    .select((user_restrictions::target_id, user_restrictions::kind))
+    .load::<(i32, String)>(&conn)
+}
+
+/// Authors whose articles the user should not be shown: those they blocked
+/// or muted, and those who blocked them.
+#[tracing::instrument(skip_all, fields(user_id = given_id))]
+pub fn get_hidden_author_ids(pool: &DbPool, given_id: &i32) -> QueryResult<Vec<i32>> {
+    let conn = pool.get().unwrap();
+    let mut hidden = user_restrictions::table
+    .filter(user_restrictions::user_id.eq(given_id))
+    .select(user_restrictions::target_id)
+    .load::<i32>(&conn)?;
+    hidden.extend(
+        user_restrictions::table
+        .filter(user_restrictions::target_id.eq(given_id))
+        .filter(user_restrictions::kind.eq(BLOCK))
+        .select(user_restrictions::user_id)
+        .load::<i32>(&conn)?,
+    );
+    Ok(hidden)
+}
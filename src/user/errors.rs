@@ -3,7 +3,35 @@ use juniper::{graphql_value, FieldError, IntoFieldError};
 pub enum UserError {
     InvalidUsernameOrPassword,
     Unauthorized,
-    NotFound
+    NotFound,
+    Disabled,
+    Forbidden,
+    InvalidResetToken,
+    InvalidVerificationToken,
+    EmailNotVerified,
+    UsernameTaken,
+    EmailTaken,
+    /// Login throttled or account locked; seconds until the next attempt may succeed.
+    TooManyAttempts(i32),
+    TotpAlreadyEnabled,
+    TotpNotEnabled,
+    InvalidMfaCode,
+    InvalidMfaChallenge,
+    InvalidLinkToken,
+    /// A personal access token was used without the scope the field needs.
+    InsufficientScope,
+    /// The field is only available when signed in with a password, not with a personal access token.
+    SessionRequired,
+    InvalidPersonalAccessToken,
+    /// One of the two users blocked the other.
+    Blocked,
+    /// Users cannot block or mute themselves.
+    CannotRestrictSelf,
+    /// The uploaded avatar was rejected, with why.
+    InvalidAvatar(&'static str),
+    /// The account has no password to confirm with, and the session is too
+    /// old to stand in for one.
+    RecentSignInRequired
 }
 
 impl IntoFieldError for UserError {
@@ -20,6 +48,67 @@ impl IntoFieldError for UserError {
             }) ),
             UserError::NotFound => FieldError::new("Not found", graphql_value!({
                 "code": "user.not.found"
+            }) ),
+            UserError::Disabled => FieldError::new("Account disabled", graphql_value!({
+                "code": "account.disabled"
+            }) ),
+            UserError::Forbidden => FieldError::new("Forbidden", graphql_value!({
+                "code": "forbidden"
+            }) ),
+            UserError::InvalidResetToken => FieldError::new("Invalid or expired reset token", graphql_value!({
+                "code": "invalid.reset.token"
+            }) ),
+            UserError::InvalidVerificationToken => FieldError::new("Invalid or expired verification token", graphql_value!({
+                "code": "invalid.verification.token"
+            }) ),
+            UserError::EmailNotVerified => FieldError::new("Email not verified", graphql_value!({
+                "code": "email.not.verified"
+            }) ),
+            UserError::UsernameTaken => FieldError::new("Username already taken", graphql_value!({
+                "code": "username.taken"
+            }) ),
+            UserError::EmailTaken => FieldError::new("Email already taken", graphql_value!({
+                "code": "email.taken"
+            }) ),
+            UserError::TooManyAttempts(retry_after) => FieldError::new("Too many login attempts", graphql_value!({
+                "code": "too.many.attempts",
+                "retryAfter": retry_after
+            }) ),
+            UserError::TotpAlreadyEnabled => FieldError::new("Two-factor authentication already enabled", graphql_value!({
+                "code": "totp.already.enabled"
+            }) ),
+            UserError::TotpNotEnabled => FieldError::new("Two-factor authentication not enabled", graphql_value!({
+                "code": "totp.not.enabled"
+            }) ),
+            UserError::InvalidMfaCode => FieldError::new("Invalid authentication code", graphql_value!({
+                "code": "invalid.mfa.code"
+            }) ),
+            UserError::InvalidMfaChallenge => FieldError::new("Invalid or expired MFA challenge", graphql_value!({
+                "code": "invalid.mfa.challenge"
+            }) ),
+            UserError::InvalidLinkToken => FieldError::new("Invalid or expired link token", graphql_value!({
+                "code": "invalid.link.token"
+            }) ),
+            UserError::InsufficientScope => FieldError::new("Token lacks the required scope", graphql_value!({
+                "code": "insufficient.scope"
+            }) ),
+            UserError::SessionRequired => FieldError::new("Sign in with a password to do this", graphql_value!({
+                "code": "session.required"
+            }) ),
+            UserError::InvalidPersonalAccessToken => FieldError::new("A token needs a name, at least one scope and a positive lifetime", graphql_value!({
+                "code": "invalid.personal.access.token"
+            }) ),
+            UserError::Blocked => FieldError::new("Blocked", graphql_value!({
+                "code": "user.blocked"
+            }) ),
+            UserError::CannotRestrictSelf => FieldError::new("You cannot block or mute yourself", graphql_value!({
+                "code": "cannot.restrict.self"
+            }) ),
+            UserError::InvalidAvatar(reason) => FieldError::new(reason, graphql_value!({
+                "code": "invalid.avatar"
+            }) ),
+            UserError::RecentSignInRequired => FieldError::new("Sign in again to do this", graphql_value!({
+                "code": "recent.sign.in.required"
             }) )
         }
     }
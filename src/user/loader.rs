@@ -0,0 +1,114 @@
+//! Follow data of the profiles in a request, loaded in batches. Lists of
+//! profiles (article authors, followers...) `prime` the loader with their
+//! user ids, and the first profile field that needs follow data loads it
+//! for all of them at once, so a page costs one query per field instead of
+//! one per profile.
+
+use super::db;
+use crate::db::DbPool;
+use diesel::QueryResult;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Clone, Copy, Default)]
+pub struct FollowCounts {
+    pub followers: i32,
+    pub following: i32,
+}
+
+/// How the viewer and a user follow each other, and whether the viewer
+/// blocked or muted them.
+#[derive(Clone, Copy, Default)]
+pub struct Relation {
+    pub following: bool,
+    pub follows_you: bool,
+    pub blocking: bool,
+    pub muting: bool,
+}
+
+#[derive(Default)]
+pub struct FollowLoader {
+    primed: Mutex<HashSet<i32>>,
+    counts: Mutex<HashMap<i32, FollowCounts>>,
+    relations: Mutex<HashMap<i32, Relation>>,
+    viewer_id: OnceLock<Option<i32>>,
+}
+
+impl FollowLoader {
+    pub fn prime(&self, user_ids: impl IntoIterator<Item = i32>) {
+        self.primed.lock().unwrap().extend(user_ids);
+    }
+
+    /// The viewer whose relations are loaded, computed once per request.
+    pub fn viewer_id(&self, authenticate: impl FnOnce() -> Option<i32>) -> Option<i32> {
+        *self.viewer_id.get_or_init(authenticate)
+    }
+
+    /// `user_id` and every primed user not loaded into `loaded` yet.
+    fn batch<T>(&self, user_id: i32, loaded: &HashMap<i32, T>) -> Vec<i32> {
+        let primed = self.primed.lock().unwrap();
+        primed
+            .iter()
+            .copied()
+            .chain(std::iter::once(user_id))
+            .filter(|id| !loaded.contains_key(id))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn counts(&self, pool: &DbPool, user_id: i32) -> QueryResult<FollowCounts> {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(found) = counts.get(&user_id) {
+            return Ok(*found);
+        }
+        let batch = self.batch(user_id, &counts);
+        let mut loaded = batch
+            .iter()
+            .map(|id| (*id, FollowCounts::default()))
+            .collect::<HashMap<_, _>>();
+        for (id, count) in db::count_followers(pool, &batch)? {
+            loaded.entry(id).or_default().followers = count as i32;
+        }
+        for (id, count) in db::count_following(pool, &batch)? {
+            loaded.entry(id).or_default().following = count as i32;
+        }
+        counts.extend(loaded);
+        Ok(counts[&user_id])
+    }
+
+    pub fn relation(&self, pool: &DbPool, viewer_id: i32, user_id: i32) -> QueryResult<Relation> {
+        let mut relations = self.relations.lock().unwrap();
+        if let Some(found) = relations.get(&user_id) {
+            return Ok(*found);
+        }
+        let batch = self.batch(user_id, &relations);
+        let mut loaded = batch
+            .iter()
+            .map(|id| (*id, Relation::default()))
+            .collect::<HashMap<_, _>>();
+        for id in db::get_followed_among(pool, &viewer_id, &batch)? {
+            loaded.entry(id).or_default().following = true;
+        }
+        for id in db::get_followers_among(pool, &viewer_id, &batch)? {
+            loaded.entry(id).or_default().follows_you = true;
+        }
+        for (id, kind) in db::get_restrictions_among(pool, &viewer_id, &batch)? {
+            let relation = loaded.entry(id).or_default();
+            match kind.as_str() {
+                db::BLOCK => relation.blocking = true,
+                db::MUTE => relation.muting = true,
+                _ => {}
+            }
+        }
+        relations.extend(loaded);
+        Ok(relations[&user_id])
+    }
+
+    /// Drops what was loaded about a user, after the viewer followed,
+    /// blocked or muted them (or undid it).
+    pub fn forget(&self, user_id: i32) {
+        self.counts.lock().unwrap().remove(&user_id);
+        self.relations.lock().unwrap().remove(&user_id);
+    }
+}
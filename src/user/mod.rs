@@ -1,5 +1,10 @@
+#[allow(non_local_definitions)] // diesel 1.4 derives and macros
 pub mod db;
 pub mod model;
 pub mod resolvers;
 pub mod errors;
 pub mod auth;
+pub mod totp;
+pub mod sessions;
+pub mod loader;
+pub mod avatar;
@@ -1,22 +1,423 @@
-use juniper::GraphQLObject;
+use super::auth;
+use super::db::{self, UserEntity};
+use super::loader::Relation;
+use crate::article::model::ArticleStatus;
+use crate::errors::internal_server_error;
+use crate::pagination::{self, PageInfo};
+use crate::schema::Context;
+use chrono::{DateTime, Utc};
+use juniper::{FieldResult, GraphQLEnum, GraphQLObject};
+use serde::Deserialize;
+
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[graphql(description = "What a user is allowed to do, each role including the ones before it")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Unknown values fall back to the least privileged role.
+    pub fn parse(role: &str) -> Role {
+        match role {
+            "admin" => Role::Admin,
+            "moderator" => Role::Moderator,
+            _ => Role::User,
+        }
+    }
+}
+
+#[derive(GraphQLEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[graphql(description = "Who may see the bio and image of a profile")]
+pub enum ProfileVisibility {
+    Public,
+    #[graphql(description = "Only the user's followers")]
+    Followers,
+}
+
+impl ProfileVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProfileVisibility::Public => "public",
+            ProfileVisibility::Followers => "followers",
+        }
+    }
+
+    /// Unknown values fall back to the most restrictive visibility.
+    pub fn parse(visibility: &str) -> ProfileVisibility {
+        match visibility {
+            "public" => ProfileVisibility::Public,
+            _ => ProfileVisibility::Followers,
+        }
+    }
+}
+
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[graphql(description = "What a personal access token may be used for")]
+pub enum Scope {
+    #[graphql(description = "articles:read, the feed and favorites")]
+    ArticlesRead,
+    #[graphql(description = "articles:write, creating, updating and deleting articles")]
+    ArticlesWrite,
+    #[graphql(description = "profile:read, profiles and whether they are followed")]
+    ProfileRead,
+    #[graphql(description = "follows:write, following and unfollowing users")]
+    FollowsWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ArticlesRead => "articles:read",
+            Scope::ArticlesWrite => "articles:write",
+            Scope::ProfileRead => "profile:read",
+            Scope::FollowsWrite => "follows:write",
+        }
+    }
+
+    /// Unknown values are dropped, so a scope removed in a later version
+    /// grants nothing.
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "articles:read" => Some(Scope::ArticlesRead),
+            "articles:write" => Some(Scope::ArticlesWrite),
+            "profile:read" => Some(Scope::ProfileRead),
+            "follows:write" => Some(Scope::FollowsWrite),
+            _ => None,
+        }
+    }
+}
 
 #[derive(GraphQLObject)]
 #[graphql(description = "A user of the app")]
 pub struct User {
     pub email: String,
+    pub email_verified: bool,
+    #[graphql(description = "New address waiting to be confirmed through the link sent to it")]
+    pub pending_email: Option<String>,
     pub username: String,
     pub bio: Option<String>,
     pub image: Option<String>,
-    pub token: String,
+    #[graphql(description = "Null while a second factor is required, see `mfaChallenge`")]
+    pub token: Option<String>,
+    pub role: Role,
+    pub profile_visibility: ProfileVisibility,
+    pub totp_enabled: bool,
+    #[graphql(description = "Set by `authenticate` when TOTP is enabled; exchange it with `completeMfa`")]
+    pub mfa_challenge: Option<MfaChallenge>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Proof that the password was checked, waiting for a second factor")]
+pub struct MfaChallenge {
+    pub challenge: String,
+    pub expires_at: DateTime<Utc>,
 }
 
 #[derive(GraphQLObject)]
-#[graphql(description = "The profile of a user")]
+#[graphql(description = "What to show an authenticator app to enroll it")]
+pub struct TotpSetup {
+    #[graphql(description = "Base32 secret, for manual entry")]
+    pub secret: String,
+    #[graphql(description = "otpauth:// URI to render as a QR code")]
+    pub provisioning_uri: String,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A device or browser the user signed in from")]
+pub struct Session {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    #[graphql(description = "Whether this is the session making the request")]
+    pub current: bool,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A long-lived token for scripts and bots, limited to its scopes")]
+pub struct PersonalAccessToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[graphql(description = "Null for tokens that never expire")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A new personal access token and its secret")]
+pub struct NewPersonalAccessToken {
+    #[graphql(description = "Send as `Authorization: Bearer <token>`; it is not shown again")]
+    pub token: String,
+    pub personal_access_token: PersonalAccessToken,
+}
+
+/// The profile of a user. Follow data comes from the request's
+/// `FollowLoader`, which lists of profiles should prime with their ids.
 pub struct Profile {
+    pub id: i32,
     pub username: String,
     pub bio: Option<String>,
     pub image: Option<String>,
-    pub following: bool,
+    pub visibility: ProfileVisibility,
+}
+
+impl Profile {
+    fn viewer_id(context: &Context) -> Option<i32> {
+        context.follow_loader.viewer_id(|| {
+            auth::get_id_from_token(&context.db_pool, &context.token, Scope::ProfileRead).ok()
+        })
+    }
+
+    fn relation(&self, context: &Context) -> FieldResult<Relation> {
+        match Profile::viewer_id(context) {
+            Some(viewer_id) => context
+                .follow_loader
+                .relation(&context.db_pool, viewer_id, self.id)
+                .map_err(internal_server_error),
+            None => Ok(Relation::default()),
+        }
+    }
+
+    /// Whether the viewer may see the bio and image.
+    fn is_visible(&self, context: &Context) -> FieldResult<bool> {
+        match self.visibility {
+            ProfileVisibility::Public => Ok(true),
+            ProfileVisibility::Followers => Ok(Profile::viewer_id(context) == Some(self.id)
+                || self.relation(context)?.following),
+        }
+    }
+
+    fn connection(
+        context: &Context,
+        first: Option<i32>,
+        after: Option<String>,
+        load: impl FnOnce(Option<i32>, i64) -> diesel::QueryResult<Vec<UserEntity>>,
+    ) -> FieldResult<ProfileConnection> {
+        let page_size = pagination::page_size(first);
+        let after = match after {
+            Some(cursor) => Some(pagination::decode_cursor(&cursor)? as i32),
+            None => None,
+        };
+        let users = load(after, page_size + 1).map_err(internal_server_error)?;
+        let (users, has_next_page) = pagination::split_page(users, page_size);
+        let keys = users.iter().map(|user| user.id as i64).collect::<Vec<_>>();
+        Ok(Profile::edges(context, users, keys, has_next_page))
+    }
+
+    /// A connection over a ranking, whose cursors are positions in it since
+    /// ranks are not unique.
+    pub fn ranked_connection(
+        context: &Context,
+        first: Option<i32>,
+        after: Option<String>,
+        load: impl FnOnce(i64, i64) -> diesel::QueryResult<Vec<UserEntity>>,
+    ) -> FieldResult<ProfileConnection> {
+        let page_size = pagination::page_size(first);
+        let offset = match after {
+            Some(cursor) => pagination::decode_cursor(&cursor)?,
+            None => 0,
+        };
+        let users = load(offset, page_size + 1).map_err(internal_server_error)?;
+        let (users, has_next_page) = pagination::split_page(users, page_size);
+        let keys = (offset + 1..).take(users.len()).collect::<Vec<_>>();
+        Ok(Profile::edges(context, users, keys, has_next_page))
+    }
+
+    fn edges(context: &Context, users: Vec<UserEntity>, keys: Vec<i64>, has_next_page: bool) -> ProfileConnection {
+        context.follow_loader.prime(users.iter().map(|user| user.id));
+        let edges = users
+            .into_iter()
+            .zip(keys)
+            .map(|(user, key)| ProfileEdge {
+                cursor: pagination::encode_cursor(key),
+                node: Profile::from(user),
+            })
+            .collect::<Vec<_>>();
+        ProfileConnection {
+            page_info: PageInfo {
+                has_next_page,
+                end_cursor: edges.last().map(|edge| edge.cursor.clone()),
+            },
+            edges,
+        }
+    }
 }
 
+#[juniper::graphql_object(Context = Context, description = "The profile of a user")]
+impl Profile {
+    fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Null when the user shows it to their followers only and the viewer
+    /// is not one.
+    fn bio(&self, context: &Context) -> FieldResult<Option<&str>> {
+        let _span = tracing::info_span!("Profile.bio").entered();
+        Ok(if self.is_visible(context)? { self.bio.as_deref() } else { None })
+    }
+
+    /// Null when the user shows it to their followers only and the viewer
+    /// is not one.
+    fn image(&self, context: &Context) -> FieldResult<Option<&str>> {
+        let _span = tracing::info_span!("Profile.image").entered();
+        Ok(if self.is_visible(context)? { self.image.as_deref() } else { None })
+    }
+
+    /// Whether the viewer follows this user.
+    fn following(&self, context: &Context) -> FieldResult<bool> {
+        let _span = tracing::info_span!("Profile.following").entered();
+        Ok(self.relation(context)?.following)
+    }
+
+    /// Whether this user follows the viewer.
+    fn follows_you(&self, context: &Context) -> FieldResult<bool> {
+        let _span = tracing::info_span!("Profile.followsYou").entered();
+        Ok(self.relation(context)?.follows_you)
+    }
+
+    /// Whether the viewer blocked this user.
+    fn blocking(&self, context: &Context) -> FieldResult<bool> {
+        let _span = tracing::info_span!("Profile.blocking").entered();
+        Ok(self.relation(context)?.blocking)
+    }
+
+    /// Whether the viewer muted this user.
+    fn muting(&self, context: &Context) -> FieldResult<bool> {
+        let _span = tracing::info_span!("Profile.muting").entered();
+        Ok(self.relation(context)?.muting)
+    }
+
+    fn followers_count(&self, context: &Context) -> FieldResult<i32> {
+        let _span = tracing::info_span!("Profile.followersCount").entered();
+        let counts = context
+            .follow_loader
+            .counts(&context.db_pool, self.id)
+            .map_err(internal_server_error)?;
+        Ok(counts.followers)
+    }
+
+    fn following_count(&self, context: &Context) -> FieldResult<i32> {
+        let _span = tracing::info_span!("Profile.followingCount").entered();
+        let counts = context
+            .follow_loader
+            .counts(&context.db_pool, self.id)
+            .map_err(internal_server_error)?;
+        Ok(counts.following)
+    }
+
+    fn followers(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> FieldResult<ProfileConnection> {
+        let _span = tracing::info_span!("Profile.followers").entered();
+        Profile::connection(context, first, after, |after, limit| {
+            db::get_followers_page(&context.db_pool, &self.id, after, limit)
+        })
+    }
 
+    /// The users this user follows (`following` tells whether the viewer
+    /// follows this user).
+    fn followed_users(
+        &self,
+        context: &Context,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> FieldResult<ProfileConnection> {
+        let _span = tracing::info_span!("Profile.followedUsers").entered();
+        Profile::connection(context, first, after, |after, limit| {
+            db::get_followed_page(&context.db_pool, &self.id, after, limit)
+        })
+    }
+}
+
+impl From<UserEntity> for Profile {
+    fn from(user: UserEntity) -> Self {
+        Profile {
+            id: user.id,
+            username: user.username,
+            bio: user.bio,
+            image: user.image,
+            visibility: ProfileVisibility::parse(&user.profile_visibility),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(context = Context, description = "A page of profiles")]
+pub struct ProfileConnection {
+    pub edges: Vec<ProfileEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(context = Context)]
+pub struct ProfileEdge {
+    pub cursor: String,
+    pub node: Profile,
+}
+
+
+
+#[derive(GraphQLObject)]
+#[graphql(description = "An audited change of a user's role")]
+pub struct RoleChange {
+    pub id: i32,
+    pub username: Option<String>,
+    pub old_role: Role,
+    pub new_role: Role,
+    pub changed_by: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Everything stored about the viewer, see `exportMyData`")]
+pub struct DataExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: ExportedProfile,
+    pub articles: Vec<ExportedArticle>,
+    #[graphql(description = "Slugs of the articles the user favorited")]
+    pub favorites: Vec<String>,
+    #[graphql(description = "Usernames of the users the user follows")]
+    pub following: Vec<String>,
+    #[graphql(description = "Usernames of the users following the user")]
+    pub followers: Vec<String>,
+}
+
+#[derive(GraphQLObject)]
+pub struct ExportedProfile {
+    pub username: String,
+    pub email: String,
+    pub bio: Option<String>,
+    pub image: Option<String>,
+    pub role: Role,
+    pub profile_visibility: ProfileVisibility,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "An article of the user, whatever its status")]
+pub struct ExportedArticle {
+    pub slug: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub body: String,
+    pub status: ArticleStatus,
+    pub published_at: Option<DateTime<Utc>>,
+    pub tag_list: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
@@ -1,10 +1,21 @@
-use juniper::{FieldResult, GraphQLInputObject, IntoFieldError};
+use juniper::{FieldError, FieldResult, GraphQLInputObject, IntoFieldError};
 use serde::Deserialize;
 
 use super::auth;
-use super::db::{NewUserDTO, UserEntity, UserUpdateDTO};
-use super::model::{Profile, User};
+use super::avatar;
+use super::totp;
+use super::db::{self, NewUserDTO, PersonalAccessTokenEntity, UserEntity, UserUpdateDTO};
+use super::model::{
+    DataExport, ExportedArticle, ExportedProfile, MfaChallenge, NewPersonalAccessToken, PersonalAccessToken, Profile, ProfileConnection, ProfileVisibility, Role, RoleChange, Scope,
+    Session, TotpSetup, User,
+};
+use super::sessions::{self, Device};
+use crate::errors::internal_server_error;
+use crate::rate_limit::LoginAccount;
+use crate::mailer::{self, Email};
 use crate::schema::Context;
+use crate::upload::Upload;
+use tracing::Instrument;
 
 #[derive(GraphQLInputObject)]
 #[graphql(description = "Payload to register a user to the app")]
@@ -22,33 +33,40 @@ pub struct UserUpdate {
     username: Option<String>,
     image: Option<String>,
     bio: Option<String>,
+    profile_visibility: Option<ProfileVisibility>,
 }
 
 impl UserUpdate {
+    #[allow(clippy::wrong_self_convention)]
     fn to_entity(self, user_entity: UserEntity) -> UserUpdateDTO {
         UserUpdateDTO {
-            email: self.email.unwrap_or(user_entity.email),
+            email: user_entity.email,
             password_hash: self
                 .password
-                .map(|s| bcrypt::hash(s, bcrypt::DEFAULT_COST).unwrap())
+                .map(auth::hash_password)
                 .unwrap_or(user_entity.password_hash),
             username: self.username.unwrap_or(user_entity.username),
             image: self.image.or(user_entity.image),
             bio: self.bio.or(user_entity.bio),
+            profile_visibility: self
+                .profile_visibility
+                .map(|visibility| visibility.as_str().to_string())
+                .unwrap_or(user_entity.profile_visibility),
         }
     }
 }
 
 #[derive(GraphQLInputObject)]
-#[graphql(description = "Payload to authenticate to the app")]
+#[graphql(description = "Payload to authenticate to the app, with either the username or the email")]
 pub struct AuthPayload {
-    username: String,
+    username: Option<String>,
+    email: Option<String>,
     password: String,
 }
 
 impl From<NewUser> for NewUserDTO {
     fn from(new_user: NewUser) -> Self {
-        let hashed_pwd = bcrypt::hash(new_user.password, bcrypt::DEFAULT_COST).unwrap();
+        let hashed_pwd = auth::hash_password(new_user.password);
         Self {
             email: new_user.email,
             username: new_user.username,
@@ -62,67 +80,346 @@ impl From<UserEntity> for User {
         Self {
             username: user_entity.username,
             email: user_entity.email,
+            email_verified: user_entity.email_verified_at.is_some(),
+            pending_email: user_entity.pending_email,
             bio: user_entity.bio,
             image: user_entity.image,
-            token: auth::get_token(user_entity.id),
+            token: None,
+            role: Role::parse(&user_entity.role),
+            profile_visibility: ProfileVisibility::parse(&user_entity.profile_visibility),
+            totp_enabled: user_entity.totp_enabled_at.is_some(),
+            mfa_challenge: None,
+        }
+    }
+}
+
+impl From<PersonalAccessTokenEntity> for PersonalAccessToken {
+    fn from(entity: PersonalAccessTokenEntity) -> Self {
+        Self {
+            id: entity.id,
+            name: entity.name,
+            scopes: entity.scopes.iter().filter_map(|scope| Scope::parse(scope)).collect(),
+            created_at: entity.created_at,
+            last_used_at: entity.last_used_at,
+            expires_at: entity.expires_at,
+        }
+    }
+}
+
+/// How long a password reset link stays valid, from `PASSWORD_RESET_TTL_MINUTES`.
+fn password_reset_ttl() -> chrono::Duration {
+    let minutes = std::env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(60);
+    chrono::Duration::minutes(minutes)
+}
+
+/// How long an email verification link stays valid, from `EMAIL_VERIFICATION_TTL_HOURS`.
+fn email_verification_ttl() -> chrono::Duration {
+    let hours = std::env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse().ok())
+        .unwrap_or(48);
+    chrono::Duration::hours(hours)
+}
+
+/// Stores a verification token for `address` and emails its link there.
+fn send_email_verification(context: &Context, user: &UserEntity, address: String) -> FieldResult<()> {
+    use super::db::{create_email_verification_token, NewEmailVerificationTokenDTO};
+    let (token, token_hash) = auth::generate_secret_token();
+    let ttl = email_verification_ttl();
+    create_email_verification_token(
+        &context.db_pool,
+        NewEmailVerificationTokenDTO {
+            user_id: user.id,
+            email: address.clone(),
+            token_hash,
+            expires_at: chrono::Utc::now() + ttl,
+        },
+    )
+    .map_err(internal_server_error)?;
+    let link = format!("{}/verify-email?token={}", mailer::app_url(), token);
+    mailer::send_in_background(
+        context.mailer.clone(),
+        Email {
+            to: address,
+            subject: "Confirm your email address".to_string(),
+            body: format!(
+                "Hi {},\n\nFollow this link to confirm your email address:\n\n{}\n\nThe link expires in {} hours.\n",
+                user.username,
+                link,
+                ttl.num_hours()
+            ),
+        },
+    );
+    Ok(())
+}
+
+/// Reports a unique violation on `users` as the username or email being
+/// taken. Both are compared case-insensitively.
+fn user_write_error(e: diesel::result::Error) -> FieldError {
+    use diesel::result::{DatabaseErrorKind, Error};
+    let constraint = match &e {
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => info.constraint_name(),
+        _ => None,
+    };
+    match constraint {
+        Some("users_username_key") | Some("users_lower_username_key") => {
+            UserError::UsernameTaken.into_field_error()
+        }
+        Some("users_email_key") | Some("users_lower_email_key") => {
+            UserError::EmailTaken.into_field_error()
+        }
+        _ => internal_server_error(e),
+    }
+}
+
+/// Whole seconds left until `until`, if it is in the future.
+fn seconds_until(until: Option<chrono::DateTime<chrono::Utc>>) -> Option<i32> {
+    let remaining = until? - chrono::Utc::now();
+    if remaining <= chrono::Duration::zero() {
+        return None;
+    }
+    Some(((remaining.num_milliseconds() + 999) / 1000) as i32)
+}
+
+const MFA_CHALLENGE_TTL_SECONDS: i64 = 5 * 60;
+/// Wrong codes allowed per challenge before the password must be entered again.
+const MFA_CHALLENGE_MAX_FAILURES: i32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// The device a GraphQL request comes from.
+fn device(context: &Context) -> Device {
+    Device {
+        user_agent: context.user_agent.clone(),
+        ip: context.client_ip.clone(),
+    }
+}
+
+/// Records a new session for `user` and returns them with a token for it.
+pub fn start_session(pool: &crate::db::DbPool, user: UserEntity, device: Device) -> diesel::QueryResult<User> {
+    use super::db::{create_session, NewSessionDTO};
+    let session = create_session(
+        pool,
+        NewSessionDTO {
+            user_id: user.id,
+            user_agent: device.user_agent,
+            ip: device.ip,
+        },
+    )?;
+    let token = auth::get_token(user.id, Role::parse(&user.role), session.id);
+    let mut user = User::from(user);
+    user.token = Some(token);
+    Ok(user)
+}
+
+/// The user for a session once their password (or identity provider) has
+/// been checked: with a token, or with an MFA challenge instead when TOTP is
+/// enabled.
+pub fn sign_in(pool: &crate::db::DbPool, user: UserEntity, device: Device) -> diesel::QueryResult<User> {
+    if user.totp_enabled_at.is_none() {
+        return start_session(pool, user, device);
+    }
+    let (challenge, challenge_hash) = auth::generate_secret_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(MFA_CHALLENGE_TTL_SECONDS);
+    use super::db::{create_mfa_challenge, NewMfaChallengeDTO};
+    create_mfa_challenge(
+        pool,
+        NewMfaChallengeDTO {
+            user_id: user.id,
+            challenge_hash,
+            expires_at,
+        },
+    )?;
+    let mut user = User::from(user);
+    user.mfa_challenge = Some(MfaChallenge {
+        challenge,
+        expires_at,
+    });
+    Ok(user)
+}
+
+/// Records a login attempt in the `login_attempts` audit table. A failure to
+/// write it is logged rather than failing the login.
+fn audit_login(context: &Context, user_id: Option<i32>, login: &str, failure_reason: Option<&str>) {
+    use super::db::{record_login_attempt, NewLoginAttemptDTO};
+    let attempt = NewLoginAttemptDTO {
+        user_id,
+        login: login.to_string(),
+        ip: context.client_ip.clone(),
+        succeeded: failure_reason.is_none(),
+        failure_reason: failure_reason.map(str::to_string),
+    };
+    if let Err(e) = record_login_attempt(&context.db_pool, attempt) {
+        tracing::warn!(error = %e, "failed to record login attempt");
+    }
+}
+
+/// A recovery code such as `3f9a1-c07be`.
+fn generate_recovery_code() -> String {
+    use rand::RngCore;
+    let mut bytes = [0u8; 5];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Recovery codes are matched ignoring case, spaces and dashes.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    auth::hash_secret_token(&normalized)
+}
+
+/// Accepts a TOTP code for the user's confirmed secret or an unused
+/// recovery code, burning whichever was used.
+fn verify_second_factor(pool: &crate::db::DbPool, user: &UserEntity, code: &str) -> FieldResult<bool> {
+    use super::db::{use_recovery_code, use_totp_step};
+    if let (Some(secret), Some(_)) = (&user.totp_secret, user.totp_enabled_at) {
+        let now = chrono::Utc::now().timestamp();
+        if let Some(step) = totp::verify(secret, code, now, user.totp_last_used_step) {
+            return use_totp_step(pool, &user.id, step).map_err(internal_server_error);
         }
     }
+    use_recovery_code(pool, &user.id, &hash_recovery_code(code)).map_err(internal_server_error)
 }
 
 pub struct UsersQuery;
 
 #[juniper::graphql_object(Context = Context)]
 impl UsersQuery {
-    fn profile(context: &Context, username: String) -> FieldResult<Profile> {
-        use super::auth::decode_token;
-        if context.token.is_none() {
-            return Err(UserError::Unauthorized.into_field_error());
-        }
-        let token = context.token.as_ref().unwrap().as_str();
-        let claims_result = decode_token(token);
-        if claims_result.is_err() {
-            return Err(UserError::Unauthorized.into_field_error());
-        }
-        let claims = claims_result.unwrap().claims;
-        let id = claims.sub.parse::<i32>().unwrap();
+    /// The signed-in user.
+    fn me(context: &Context) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersQuery.me").entered();
+        let pool = &context.db_pool;
+        let id = auth::get_id_from_token(pool, &context.token, Scope::ProfileRead)?;
+        let user = db::get_user_by_id(pool, &id).map_err(internal_server_error)?;
+        Ok(User::from(user))
+    }
 
+    /// A user's public profile; `following` is false without a token.
+    fn profile(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersQuery.profile", username = %username).entered();
         let pool = &context.db_pool;
+
         use super::db::get_user_by_username;
         let user = get_user_by_username(pool, &username);
         if let Err(e) = user {
             return match e {
                 diesel::result::Error::NotFound => Err(UserError::NotFound.into_field_error()),
-                _ => {
-                    eprintln!("{}", e);
-                    use juniper::{graphql_value, FieldError};
-                    Err(FieldError::new(
-                        "Internal Server Error",
-                        graphql_value!({
-                            "code": "internal.server.error"
-                        }),
-                    ))
-                }
+                _ => Err(internal_server_error(e)),
             };
         };
-        let user = user.unwrap();
-        use super::db::get_follows;
-        let following = get_follows(pool, &id, &username);
-        if let Err(e) = following {
-            eprintln!("{}", e);
-            use juniper::{graphql_value, FieldError};
-            return Err(FieldError::new(
-                "Internal Server Error",
-                graphql_value!({
-                    "code": "internal.server.error"
-                }),
-            ));
-        };
-        let following = following.unwrap();
-        Ok(Profile {
-            username: user.username,
-            bio: user.bio,
-            image: user.image,
-            following,
+        Ok(Profile::from(user.unwrap()))
+    }
+
+    /// Users whose username or bio resembles `query`, best matches first
+    /// and then the most followed.
+    fn search_users(
+        context: &Context,
+        query: String,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> FieldResult<ProfileConnection> {
+        let _span = tracing::info_span!("UsersQuery.searchUsers", query = %query).entered();
+        let query = query.trim();
+        Profile::ranked_connection(context, first, after, |offset, limit| {
+            if query.is_empty() {
+                return Ok(Vec::new());
+            }
+            db::search_users(&context.db_pool, query, offset, limit)
+        })
+    }
+
+    /// Authors followed by the users the viewer follows, the most followed
+    /// among them first.
+    fn suggested_authors(context: &Context, first: Option<i32>) -> FieldResult<Vec<Profile>> {
+        let _span = tracing::info_span!("UsersQuery.suggestedAuthors").entered();
+        let pool = &context.db_pool;
+        let id = auth::get_id_from_token(pool, &context.token, Scope::ProfileRead)?;
+        let authors = db::get_suggested_authors(pool, &id, crate::pagination::page_size(first))
+            .map_err(internal_server_error)?;
+        context.follow_loader.viewer_id(|| Some(id));
+        context.follow_loader.prime(authors.iter().map(|author| author.id));
+        Ok(authors.into_iter().map(Profile::from).collect())
+    }
+
+    /// Where the viewer is signed in, most recently used first.
+    fn my_sessions(context: &Context) -> FieldResult<Vec<Session>> {
+        let _span = tracing::info_span!("UsersQuery.mySessions").entered();
+        let pool = &context.db_pool;
+        let viewer = auth::require_session(pool, &context.token)?;
+        use super::db::get_sessions;
+        let seen_since = chrono::Utc::now() - chrono::Duration::minutes(auth::TOKEN_TTL_MINUTES);
+        let sessions = get_sessions(pool, &viewer.id, seen_since).map_err(internal_server_error)?;
+        Ok(sessions
+            .into_iter()
+            .map(|session| Session {
+                current: viewer.session_id == Some(session.id),
+                id: session.id,
+                user_agent: session.user_agent,
+                ip: session.ip,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+            })
+            .collect())
+    }
+
+    fn access_tokens(context: &Context) -> FieldResult<Vec<PersonalAccessToken>> {
+        let _span = tracing::info_span!("UsersQuery.accessTokens").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+        use super::db::get_personal_access_tokens;
+        let tokens = get_personal_access_tokens(pool, &id).map_err(internal_server_error)?;
+        Ok(tokens.into_iter().map(PersonalAccessToken::from).collect())
+    }
+
+    /// Everything stored about the viewer, including unpublished articles.
+    fn export_my_data(context: &Context) -> FieldResult<DataExport> {
+        let _span = tracing::info_span!("UsersQuery.exportMyData").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+
+        use super::db::{get_follower_usernames, get_following_usernames, get_user_by_id};
+        use crate::article::db::{get_by_author, get_favorited_slugs, get_tags};
+        use crate::article::model::ArticleStatus;
+        let user = get_user_by_id(pool, &id).map_err(internal_server_error)?;
+        let articles = get_by_author(pool, id)
+            .map_err(internal_server_error)?
+            .into_iter()
+            .map(|article| {
+                Ok(ExportedArticle {
+                    tag_list: get_tags(pool, article.id)?,
+                    status: ArticleStatus::parse(&article.status),
+                    slug: article.slug,
+                    title: article.title,
+                    description: article.description,
+                    body: article.body,
+                    published_at: article.published_at,
+                    created_at: article.created_at,
+                    updated_at: article.updated_at,
+                })
+            })
+            .collect::<diesel::QueryResult<Vec<_>>>()
+            .map_err(internal_server_error)?;
+        Ok(DataExport {
+            exported_at: chrono::Utc::now(),
+            profile: ExportedProfile {
+                role: Role::parse(&user.role),
+                profile_visibility: ProfileVisibility::parse(&user.profile_visibility),
+                username: user.username,
+                email: user.email,
+                bio: user.bio,
+                image: user.image,
+            },
+            articles,
+            favorites: get_favorited_slugs(pool, id).map_err(internal_server_error)?,
+            following: get_following_usernames(pool, &id).map_err(internal_server_error)?,
+            followers: get_follower_usernames(pool, &id).map_err(internal_server_error)?,
         })
     }
 }
@@ -133,59 +430,445 @@ use super::errors::UserError;
 #[juniper::graphql_object(Context = Context)]
 impl UsersMutation {
     fn register_user(context: &Context, new_user: NewUser) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersMutation.registerUser").entered();
         use super::db::create;
         let pool = &context.db_pool;
-        let user = create(pool, NewUserDTO::from(new_user))?;
-        Ok(User::from(user))
+        let user = create(pool, NewUserDTO::from(new_user)).map_err(user_write_error)?;
+        send_email_verification(context, &user, user.email.clone())?;
+        start_session(pool, user, device(context)).map_err(internal_server_error)
     }
 
     fn authenticate(context: &Context, auth_payload: AuthPayload) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersMutation.authenticate", username = ?auth_payload.username).entered();
         let pool = &context.db_pool;
-        use super::db::get_user_by_username;
-        let user = get_user_by_username(pool, &auth_payload.username);
-        if let Err(e) = user {
-            return match e {
-                diesel::result::Error::NotFound => {
-                    Err(UserError::InvalidUsernameOrPassword.into_field_error())
-                }
-                _ => {
-                    eprintln!("{}", e);
-                    use juniper::{graphql_value, FieldError};
-                    Err(FieldError::new(
-                        "Internal Server Error",
-                        graphql_value!({
-                            "code": "internal.server.error"
-                        }),
-                    ))
-                }
-            };
+        let login = auth_payload
+            .username
+            .as_ref()
+            .or(auth_payload.email.as_ref())
+            .map(|login| login.to_lowercase())
+            .unwrap_or_default();
+        let record = |user_id: Option<i32>, failure_reason: Option<&str>| {
+            audit_login(context, user_id, &login, failure_reason)
         };
-        let user = user.unwrap();
-        let valid = bcrypt::verify(auth_payload.password, &user.password_hash).unwrap();
-        if valid {
-            Ok(User::from(user))
+        use super::db::{get_user_by_email, get_user_by_username};
+        let user = match (&auth_payload.username, &auth_payload.email) {
+            (Some(username), _) => get_user_by_username(pool, username),
+            (None, Some(email)) => get_user_by_email(pool, email),
+            (None, None) => Err(diesel::result::Error::NotFound),
+        };
+        let user = match user {
+            Ok(user) => Some(user),
+            Err(diesel::result::Error::NotFound) => None,
+            Err(e) => return Err(internal_server_error(e)),
+        };
+        let account = match &user {
+            Some(user) => LoginAccount::User(user.id),
+            None => LoginAccount::Unknown(&login),
+        };
+        if let Err(retry_after) = context
+            .login_rate_limiter
+            .check(context.client_ip.as_deref(), account)
+        {
+            record(user.as_ref().map(|user| user.id), Some("rate_limited"));
+            let retry_after = retry_after.as_secs_f64().ceil() as i32;
+            return Err(UserError::TooManyAttempts(retry_after).into_field_error());
+        }
+        let user = match user {
+            Some(user) => user,
+            None => {
+                record(None, Some("unknown_user"));
+                return Err(UserError::InvalidUsernameOrPassword.into_field_error());
+            }
+        };
+        if let Some(retry_after) = seconds_until(user.locked_until) {
+            record(Some(user.id), Some("locked"));
+            return Err(UserError::TooManyAttempts(retry_after).into_field_error());
+        }
+        let valid = bcrypt::verify(auth_payload.password, &user.password_hash).unwrap_or(false);
+        if !valid {
+            use super::db::{record_failed_login, LockoutPolicy};
+            let user = record_failed_login(pool, &user.id, LockoutPolicy::from_env())
+                .map_err(internal_server_error)?;
+            record(Some(user.id), Some("invalid_password"));
+            if let Some(retry_after) = seconds_until(user.locked_until) {
+                tracing::warn!(user_id = user.id, failures = user.failed_login_attempts, "account locked");
+                return Err(UserError::TooManyAttempts(retry_after).into_field_error());
+            }
+            return Err(UserError::InvalidUsernameOrPassword.into_field_error());
+        }
+        if user.disabled_at.is_some() {
+            record(Some(user.id), Some("disabled"));
+            return Err(UserError::Disabled.into_field_error());
+        }
+        let user = if user.failed_login_attempts > 0 || user.locked_until.is_some() {
+            use super::db::reset_failed_logins;
+            reset_failed_logins(pool, &user.id).map_err(internal_server_error)?
+        } else {
+            user
+        };
+        if user.totp_enabled_at.is_some() {
+            record(Some(user.id), Some("mfa_required"));
         } else {
-            Err(UserError::InvalidUsernameOrPassword.into_field_error())
+            record(Some(user.id), None);
         }
+        sign_in(pool, user, device(context)).map_err(internal_server_error)
     }
 
-    fn update_user(context: &Context, user_update: UserUpdate) -> FieldResult<User> {
+    /// Attaches an identity from a provider sign-in to the existing account
+    /// with the same email, once its owner has entered their password.
+    fn link_identity(context: &Context, link_token: String, password: String) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersMutation.linkIdentity").entered();
         let pool = &context.db_pool;
-        let id = auth::get_id_from_token(&context.token);
-        if let Err(e) = id {
-            return Err(e);
+        use crate::oidc::db::{get_link_request, link_identity};
+        let request = get_link_request(pool, &auth::hash_secret_token(&link_token))
+            .map_err(internal_server_error)?
+            .ok_or_else(|| UserError::InvalidLinkToken.into_field_error())?;
+        use super::db::get_user_by_id;
+        let user = get_user_by_id(pool, &request.user_id).map_err(internal_server_error)?;
+        let login = user.username.to_lowercase();
+        if let Err(retry_after) = context
+            .login_rate_limiter
+            .check(context.client_ip.as_deref(), LoginAccount::User(user.id))
+        {
+            audit_login(context, Some(user.id), &login, Some("rate_limited"));
+            let retry_after = retry_after.as_secs_f64().ceil() as i32;
+            return Err(UserError::TooManyAttempts(retry_after).into_field_error());
+        }
+        if let Some(retry_after) = seconds_until(user.locked_until) {
+            audit_login(context, Some(user.id), &login, Some("locked"));
+            return Err(UserError::TooManyAttempts(retry_after).into_field_error());
+        }
+        if !bcrypt::verify(password, &user.password_hash).unwrap_or(false) {
+            use super::db::{record_failed_login, LockoutPolicy};
+            record_failed_login(pool, &user.id, LockoutPolicy::from_env())
+                .map_err(internal_server_error)?;
+            audit_login(context, Some(user.id), &login, Some("invalid_password"));
+            return Err(UserError::InvalidUsernameOrPassword.into_field_error());
+        }
+        if user.disabled_at.is_some() {
+            return Err(UserError::Disabled.into_field_error());
+        }
+        if !link_identity(pool, &request).map_err(internal_server_error)? {
+            return Err(UserError::InvalidLinkToken.into_field_error());
+        }
+        tracing::info!(user_id = user.id, issuer = %request.issuer, "identity linked");
+        sign_in(pool, user, device(context)).map_err(internal_server_error)
+    }
+
+    /// Second step of `authenticate` for accounts with TOTP: exchanges the
+    /// challenge and a TOTP or recovery code for a token.
+    fn complete_mfa(context: &Context, challenge: String, code: String) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersMutation.completeMfa").entered();
+        let pool = &context.db_pool;
+        use super::db::{fail_mfa_challenge, get_mfa_challenge, get_user_by_id, use_mfa_challenge};
+        let (challenge_id, user_id) = get_mfa_challenge(
+            pool,
+            &auth::hash_secret_token(&challenge),
+            MFA_CHALLENGE_MAX_FAILURES,
+        )
+        .map_err(internal_server_error)?
+        .ok_or_else(|| UserError::InvalidMfaChallenge.into_field_error())?;
+        let user = get_user_by_id(pool, &user_id).map_err(internal_server_error)?;
+        let login = user.username.to_lowercase();
+        if let Err(retry_after) = context
+            .login_rate_limiter
+            .check(context.client_ip.as_deref(), LoginAccount::User(user.id))
+        {
+            audit_login(context, Some(user.id), &login, Some("rate_limited"));
+            let retry_after = retry_after.as_secs_f64().ceil() as i32;
+            return Err(UserError::TooManyAttempts(retry_after).into_field_error());
+        }
+        if !verify_second_factor(pool, &user, &code)? {
+            fail_mfa_challenge(pool, challenge_id).map_err(internal_server_error)?;
+            audit_login(context, Some(user.id), &login, Some("invalid_mfa_code"));
+            return Err(UserError::InvalidMfaCode.into_field_error());
+        }
+        if !use_mfa_challenge(pool, challenge_id).map_err(internal_server_error)? {
+            return Err(UserError::InvalidMfaChallenge.into_field_error());
+        }
+        if user.disabled_at.is_some() {
+            audit_login(context, Some(user.id), &login, Some("disabled"));
+            return Err(UserError::Disabled.into_field_error());
+        }
+        audit_login(context, Some(user.id), &login, None);
+        start_session(pool, user, device(context)).map_err(internal_server_error)
+    }
+
+    /// Starts TOTP enrollment. The secret is only used for logins once
+    /// `confirmTotp` has checked a first code from the authenticator app.
+    fn enable_totp(context: &Context) -> FieldResult<TotpSetup> {
+        let _span = tracing::info_span!("UsersMutation.enableTotp").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+        use super::db::{get_user_by_id, set_totp_secret};
+        let user = get_user_by_id(pool, &id).map_err(internal_server_error)?;
+        if user.totp_enabled_at.is_some() {
+            return Err(UserError::TotpAlreadyEnabled.into_field_error());
+        }
+        let secret = totp::generate_secret();
+        set_totp_secret(pool, &id, Some(secret.clone())).map_err(internal_server_error)?;
+        Ok(TotpSetup {
+            provisioning_uri: totp::provisioning_uri(&secret, &user.email, &totp::issuer()),
+            secret,
+        })
+    }
+
+    /// Finishes enrollment with a code from the authenticator app and
+    /// returns single-use recovery codes. They are not shown again.
+    fn confirm_totp(context: &Context, code: String) -> FieldResult<Vec<String>> {
+        let _span = tracing::info_span!("UsersMutation.confirmTotp").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+        use super::db::{enable_totp, get_user_by_id};
+        let user = get_user_by_id(pool, &id).map_err(internal_server_error)?;
+        if user.totp_enabled_at.is_some() {
+            return Err(UserError::TotpAlreadyEnabled.into_field_error());
+        }
+        let secret = user
+            .totp_secret
+            .ok_or_else(|| UserError::TotpNotEnabled.into_field_error())?;
+        let step = totp::verify(&secret, &code, chrono::Utc::now().timestamp(), None)
+            .ok_or_else(|| UserError::InvalidMfaCode.into_field_error())?;
+        let recovery_codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect::<Vec<_>>();
+        let hashes = recovery_codes
+            .iter()
+            .map(|code| hash_recovery_code(code))
+            .collect();
+        enable_totp(pool, &id, step, hashes).map_err(internal_server_error)?;
+        tracing::info!(user_id = id, "totp enabled");
+        Ok(recovery_codes)
+    }
+
+    /// Turns TOTP off; needs a current TOTP or recovery code.
+    fn disable_totp(context: &Context, code: String) -> FieldResult<bool> {
+        let _span = tracing::info_span!("UsersMutation.disableTotp").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+        use super::db::{disable_totp, get_user_by_id};
+        let user = get_user_by_id(pool, &id).map_err(internal_server_error)?;
+        if user.totp_enabled_at.is_none() {
+            return Err(UserError::TotpNotEnabled.into_field_error());
+        }
+        if !verify_second_factor(pool, &user, &code)? {
+            return Err(UserError::InvalidMfaCode.into_field_error());
+        }
+        disable_totp(pool, &id).map_err(internal_server_error)?;
+        tracing::info!(user_id = id, "totp disabled");
+        Ok(true)
+    }
+
+    /// Emails a reset link to the account with this address. Always returns
+    /// true so the response does not reveal which addresses are registered.
+    fn request_password_reset(context: &Context, email: String) -> FieldResult<bool> {
+        let _span = tracing::info_span!("UsersMutation.requestPasswordReset").entered();
+        let pool = &context.db_pool;
+        use super::db::{create_password_reset_token, get_user_by_email, NewPasswordResetTokenDTO};
+        let user = match get_user_by_email(pool, &email) {
+            Ok(user) if user.disabled_at.is_none() => user,
+            Ok(_) | Err(diesel::result::Error::NotFound) => return Ok(true),
+            Err(e) => return Err(internal_server_error(e)),
         };
-        let id = id.unwrap();
+        let (token, token_hash) = auth::generate_secret_token();
+        let ttl = password_reset_ttl();
+        create_password_reset_token(
+            pool,
+            NewPasswordResetTokenDTO {
+                user_id: user.id,
+                token_hash,
+                expires_at: chrono::Utc::now() + ttl,
+            },
+        )
+        .map_err(internal_server_error)?;
+        let link = format!("{}/reset-password?token={}", mailer::app_url(), token);
+        mailer::send_in_background(
+            context.mailer.clone(),
+            Email {
+                to: user.email,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\nFollow this link to choose a new password:\n\n{}\n\nThe link expires in {} minutes. If you did not ask for a reset, you can ignore this email.\n",
+                    user.username,
+                    link,
+                    ttl.num_minutes()
+                ),
+            },
+        );
+        tracing::info!(user_id = user.id, "password reset requested");
+        Ok(true)
+    }
+
+    /// Sets a new password with a token from `requestPasswordReset`. Every
+    /// token issued before the reset stops working.
+    fn reset_password(context: &Context, token: String, new_password: String) -> FieldResult<bool> {
+        let _span = tracing::info_span!("UsersMutation.resetPassword").entered();
+        let pool = &context.db_pool;
+        use super::db::reset_password;
+        let user = reset_password(
+            pool,
+            &auth::hash_secret_token(&token),
+            auth::hash_password(new_password),
+        )
+        .map_err(internal_server_error)?
+        .ok_or_else(|| UserError::InvalidResetToken.into_field_error())?;
+        sessions::cache().forget_user(user.id);
+        tracing::info!(user_id = user.id, "password reset");
+        Ok(true)
+    }
+
+    /// Confirms the address a verification link was sent to. For a pending
+    /// email change, this is when the email is actually replaced.
+    fn verify_email(context: &Context, token: String) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersMutation.verifyEmail").entered();
+        let pool = &context.db_pool;
+        use super::db::verify_email;
+        let user = verify_email(pool, &auth::hash_secret_token(&token))
+            .map_err(user_write_error)?
+            .ok_or_else(|| UserError::InvalidVerificationToken.into_field_error())?;
+        tracing::info!(user_id = user.id, "email verified");
+        start_session(pool, user, device(context)).map_err(internal_server_error)
+    }
+
+    /// Sends a new verification link for the pending email, or for the
+    /// current one if it is not verified yet. Returns false when there is
+    /// nothing to verify.
+    fn resend_email_verification(context: &Context) -> FieldResult<bool> {
+        let _span = tracing::info_span!("UsersMutation.resendEmailVerification").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+        use super::db::get_user_by_id;
+        let user = get_user_by_id(pool, &id).map_err(internal_server_error)?;
+        let address = match (&user.pending_email, user.email_verified_at) {
+            (Some(pending), _) => pending.clone(),
+            (None, None) => user.email.clone(),
+            (None, Some(_)) => return Ok(false),
+        };
+        send_email_verification(context, &user, address)?;
+        Ok(true)
+    }
+
+    /// Signs a device out; revoking the current session signs out. Returns
+    /// false if the viewer has no such live session.
+    fn revoke_session(context: &Context, id: i32) -> FieldResult<bool> {
+        let _span = tracing::info_span!("UsersMutation.revokeSession", session_id = id).entered();
+        let pool = &context.db_pool;
+        let user_id = auth::require_session(pool, &context.token)?.id;
+        use super::db::revoke_session;
+        let revoked = revoke_session(pool, &user_id, id).map_err(internal_server_error)?;
+        if revoked {
+            sessions::cache().forget(id);
+            tracing::info!(user_id, session_id = id, "session revoked");
+        }
+        Ok(revoked)
+    }
+
+    /// Creates a token for scripts and bots that never need a password.
+    /// Without `expiresInDays` it lasts until revoked.
+    fn create_access_token(
+        context: &Context,
+        name: String,
+        scopes: Vec<Scope>,
+        expires_in_days: Option<i32>,
+    ) -> FieldResult<NewPersonalAccessToken> {
+        let _span = tracing::info_span!("UsersMutation.createAccessToken").entered();
+        let pool = &context.db_pool;
+        let id = auth::require_session(pool, &context.token)?.id;
+        let name = name.trim().to_string();
+        if name.is_empty() || scopes.is_empty() || expires_in_days.is_some_and(|days| days <= 0) {
+            return Err(UserError::InvalidPersonalAccessToken.into_field_error());
+        }
+        let mut scope_names = scopes.iter().map(|scope| scope.as_str().to_string()).collect::<Vec<_>>();
+        scope_names.sort();
+        scope_names.dedup();
+        let token = format!(
+            "{}{}",
+            auth::PERSONAL_ACCESS_TOKEN_PREFIX,
+            auth::generate_secret_token().0
+        );
+        use super::db::{create_personal_access_token, NewPersonalAccessTokenDTO};
+        let created = create_personal_access_token(
+            pool,
+            NewPersonalAccessTokenDTO {
+                user_id: id,
+                name,
+                token_hash: auth::hash_secret_token(&token),
+                scopes: scope_names,
+                expires_at: expires_in_days
+                    .map(|days| chrono::Utc::now() + chrono::Duration::days(days as i64)),
+            },
+        )
+        .map_err(internal_server_error)?;
+        tracing::info!(user_id = id, token_id = created.id, "personal access token created");
+        Ok(NewPersonalAccessToken {
+            token,
+            personal_access_token: PersonalAccessToken::from(created),
+        })
+    }
+
+    /// Returns false if the viewer has no such token.
+    fn revoke_access_token(context: &Context, id: i32) -> FieldResult<bool> {
+        let _span = tracing::info_span!("UsersMutation.revokeAccessToken", token_id = id).entered();
+        let pool = &context.db_pool;
+        let user_id = auth::require_session(pool, &context.token)?.id;
+        use super::db::revoke_personal_access_token;
+        let revoked = revoke_personal_access_token(pool, &user_id, id).map_err(internal_server_error)?;
+        if revoked {
+            sessions::cache().forget_token(id);
+            tracing::info!(user_id, token_id = id, "personal access token revoked");
+        }
+        Ok(revoked)
+    }
+
+    /// Deletes the viewer's account, or anonymizes it as configured, along
+    /// with their avatar files. Accounts registered through a provider have no
+    /// password to confirm with: they need a session signed in within the
+    /// last 10 minutes instead, and may omit `password`.
+    async fn delete_account(context: &Context, password: Option<String>) -> FieldResult<bool> {
+        let span = tracing::info_span!("UsersMutation.deleteAccount");
+        delete_account(context, password).instrument(span).await
+    }
+
+    fn update_user(context: &Context, user_update: UserUpdate) -> FieldResult<User> {
+        let _span = tracing::info_span!("UsersMutation.updateUser").entered();
+        let pool = &context.db_pool;
+        let viewer = auth::require_session(pool, &context.token)?;
+        let id = viewer.id;
         use super::db::get_user_by_id;
         let user = get_user_by_id(pool, &id).unwrap();
+        let new_email = user_update.email.clone();
         let update_user_dto = user_update.to_entity(user);
-        let updated_user = super::db::update_user(pool, update_user_dto, &id).unwrap();
-        Ok(User::from(updated_user))
+        let mut updated_user =
+            super::db::update_user(pool, update_user_dto, &id).map_err(user_write_error)?;
+        if let Some(new_email) = new_email {
+            use super::db::set_pending_email;
+            if new_email == updated_user.email {
+                updated_user = set_pending_email(pool, &id, None).map_err(internal_server_error)?;
+            } else if updated_user.pending_email.as_ref() != Some(&new_email) {
+                updated_user = set_pending_email(pool, &id, Some(new_email.clone()))
+                    .map_err(internal_server_error)?;
+                send_email_verification(context, &updated_user, new_email)?;
+            }
+        }
+        let role = Role::parse(&updated_user.role);
+        let mut user = User::from(updated_user);
+        user.token = viewer
+            .session_id
+            .map(|session_id| auth::get_token(id, role, session_id));
+        Ok(user)
+    }
+
+    /// Replaces the viewer's avatar with a PNG, JPEG, WebP or GIF image sent
+    /// as a multipart upload. `image` becomes the URL of its 256px variant.
+    async fn upload_avatar(context: &Context, file: Upload) -> FieldResult<User> {
+        let span = tracing::info_span!("UsersMutation.uploadAvatar");
+        upload_avatar(context, file).instrument(span).await
     }
 
     fn follow(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersMutation.follow", username = %username).entered();
         let pool = &context.db_pool;
-        let id = auth::get_id_from_token(&context.token);
+        let id = auth::get_id_from_token(&context.db_pool, &context.token, Scope::FollowsWrite);
         if let Err(e) = id {
             return Err(e);
         };
@@ -196,42 +879,27 @@ impl UsersMutation {
         if let Err(e) = user {
             return match e {
                 diesel::result::Error::NotFound => Err(UserError::NotFound.into_field_error()),
-                _ => {
-                    eprintln!("{}", e);
-                    use juniper::{graphql_value, FieldError};
-                    Err(FieldError::new(
-                        "Internal Server Error",
-                        graphql_value!({
-                            "code": "internal.server.error"
-                        }),
-                    ))
-                }
+                _ => Err(internal_server_error(e)),
             };
         };
         let user = user.unwrap();
         use super::db::follow;
         let exec_result = follow(pool, &id, &username);
-        if let Err(e) = exec_result {
-            eprintln!("{}", e);
-            use juniper::{graphql_value, FieldError};
-            return Err(FieldError::new(
-                "Internal Server Error",
-                graphql_value!({
-                    "code": "internal.server.error"
-                }),
-            ));
+        match exec_result {
+            Ok(true) => {}
+            Ok(false) => return Err(UserError::Blocked.into_field_error()),
+            Err(e) => return Err(internal_server_error(e)),
         };
-        Ok(Profile {
-            username,
-            bio: user.bio,
-            image: user.image,
-            following: true,
-        })
+        context.follow_loader.viewer_id(|| Some(id));
+        context.follow_loader.forget(id);
+        context.follow_loader.forget(user.id);
+        Ok(Profile::from(user))
     }
 
     fn unfollow(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersMutation.unfollow", username = %username).entered();
         let pool = &context.db_pool;
-        let id = auth::get_id_from_token(&context.token);
+        let id = auth::get_id_from_token(&context.db_pool, &context.token, Scope::FollowsWrite);
         if let Err(e) = id {
             return Err(e);
         };
@@ -242,37 +910,240 @@ impl UsersMutation {
         if let Err(e) = user {
             return match e {
                 diesel::result::Error::NotFound => Err(UserError::NotFound.into_field_error()),
-                _ => {
-                    eprintln!("{}", e);
-                    use juniper::{graphql_value, FieldError};
-                    Err(FieldError::new(
-                        "Internal Server Error",
-                        graphql_value!({
-                            "code": "internal.server.error"
-                        }),
-                    ))
-                }
+                _ => Err(internal_server_error(e)),
             };
         };
         let user = user.unwrap();
         use super::db::unfollow;
         let exec_result = unfollow(pool, &id, &username);
         if let Err(e) = exec_result {
-            eprintln!("{}", e);
-            use juniper::{graphql_value, FieldError};
-            return Err(FieldError::new(
-                "Internal Server Error",
-                graphql_value!({
-                    "code": "internal.server.error"
-                }),
-            ));
+            return Err(internal_server_error(e));
         };
-        Ok(Profile {
-            username,
-            bio: user.bio,
-            image: user.image,
-            following: false,
-        })
+        context.follow_loader.viewer_id(|| Some(id));
+        context.follow_loader.forget(id);
+        context.follow_loader.forget(user.id);
+        Ok(Profile::from(user))
+    }
+
+    /// Hides the viewer's articles from the user and ends the follows between
+    /// them; neither can follow the other until unblocked.
+    fn block_user(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersMutation.blockUser", username = %username).entered();
+        restrict(context, &username, db::BLOCK, true)
+    }
+
+    fn unblock_user(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersMutation.unblockUser", username = %username).entered();
+        restrict(context, &username, db::BLOCK, false)
+    }
+
+    /// Keeps the user's articles out of the viewer's feed and article lists.
+    fn mute_user(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersMutation.muteUser", username = %username).entered();
+        restrict(context, &username, db::MUTE, true)
+    }
+
+    fn unmute_user(context: &Context, username: String) -> FieldResult<Profile> {
+        let _span = tracing::info_span!("UsersMutation.unmuteUser", username = %username).entered();
+        restrict(context, &username, db::MUTE, false)
+    }
+}
+
+/// How recent a session must be to delete an account without a password.
+const RECENT_SIGN_IN_MINUTES: i64 = 10;
+
+async fn delete_account(context: &Context, password: Option<String>) -> FieldResult<bool> {
+    let pool = &context.db_pool;
+    let viewer = auth::require_session(pool, &context.token)?;
+    let id = viewer.id;
+    let user = db::get_user_by_id(pool, &id).map_err(|e| match e {
+        diesel::result::Error::NotFound => UserError::Unauthorized.into_field_error(),
+        e => internal_server_error(e),
+    })?;
+    if user.password_hash.is_empty() {
+        let session_id = viewer
+            .session_id
+            .ok_or_else(|| UserError::SessionRequired.into_field_error())?;
+        let signed_in_at = db::get_session_created_at(pool, session_id).map_err(internal_server_error)?;
+        if signed_in_at < chrono::Utc::now() - chrono::Duration::minutes(RECENT_SIGN_IN_MINUTES) {
+            return Err(UserError::RecentSignInRequired.into_field_error());
+        }
+    } else if !password.is_some_and(|password| bcrypt::verify(password, &user.password_hash).unwrap_or(false)) {
+        return Err(UserError::InvalidUsernameOrPassword.into_field_error());
+    }
+    // Collected first: both policies clear `image`.
+    let avatar_keys = user
+        .image
+        .as_deref()
+        .and_then(|url| context.blob_store.key(url))
+        .map(|key| avatar::variant_keys(&key, id))
+        .unwrap_or_default();
+    use super::db::{anonymize_user, delete_user, DeletionPolicy};
+    let policy = DeletionPolicy::from_env();
+    match policy {
+        DeletionPolicy::Delete => delete_user(pool, &id),
+        DeletionPolicy::Anonymize => anonymize_user(pool, &id),
+    }
+    .map_err(internal_server_error)?;
+    sessions::cache().forget_user(id);
+    for key in avatar_keys {
+        if let Err(e) = context.blob_store.delete(&key).await {
+            tracing::warn!(error = %e, key = %key, "failed to delete avatar of deleted account");
+        }
+    }
+    tracing::info!(user_id = id, policy = ?policy, "account deleted");
+    Ok(true)
+}
+
+async fn upload_avatar(context: &Context, file: Upload) -> FieldResult<User> {
+    let (pool, token) = (context.db_pool.clone(), context.token.clone());
+    let viewer = actix_web::web::block(move || auth::require_session(&pool, &token))
+        .await
+        .map_err(internal_server_error)??;
+    let file = context
+        .uploads
+        .take(&file)
+        .ok_or_else(|| UserError::InvalidAvatar("Avatar file is missing").into_field_error())?;
+    avatar::check(&file).map_err(IntoFieldError::into_field_error)?;
+    let variants = actix_web::web::block(move || avatar::variants(&file.bytes))
+        .await
+        .map_err(internal_server_error)?
+        .map_err(IntoFieldError::into_field_error)?;
+
+    let upload_id = uuid::Uuid::new_v4().to_string();
+    let mut url = None;
+    for (size, bytes) in variants {
+        let key = avatar::key(viewer.id, &upload_id, size);
+        context
+            .blob_store
+            .put(&key, "image/png", bytes)
+            .await
+            .map_err(internal_server_error)?;
+        url = Some(context.blob_store.url(&key));
     }
+    let pool = context.db_pool.clone();
+    let (previous, user) = actix_web::web::block(move || {
+        let previous = db::get_user_by_id(&pool, &viewer.id)?;
+        let user = db::set_image(&pool, &viewer.id, url)?;
+        Ok::<_, diesel::result::Error>((previous, user))
+    })
+    .await
+    .map_err(internal_server_error)?
+    .map_err(internal_server_error)?;
+
+    let previous_keys = previous
+        .image
+        .and_then(|url| context.blob_store.key(&url))
+        .map(|key| avatar::variant_keys(&key, viewer.id))
+        .unwrap_or_default();
+    for key in previous_keys {
+        if let Err(e) = context.blob_store.delete(&key).await {
+            tracing::warn!(error = %e, key = %key, "failed to delete previous avatar");
+        }
+    }
+    tracing::info!(user_id = viewer.id, "avatar uploaded");
+    Ok(User::from(user))
+}
+
+/// Adds or removes one of the viewer's blocks or mutes.
+fn restrict(context: &Context, username: &String, kind: &str, enable: bool) -> FieldResult<Profile> {
+    let pool = &context.db_pool;
+    let viewer = auth::require_session(pool, &context.token)?;
+    let user = match db::get_user_by_username(pool, username) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => return Err(UserError::NotFound.into_field_error()),
+        Err(e) => return Err(internal_server_error(e)),
+    };
+    if user.id == viewer.id {
+        return Err(UserError::CannotRestrictSelf.into_field_error());
+    }
+    if enable {
+        db::restrict(pool, &viewer.id, &user.id, kind).map_err(internal_server_error)?;
+    } else {
+        db::unrestrict(pool, &viewer.id, &user.id, kind).map_err(internal_server_error)?;
+    }
+    context.follow_loader.viewer_id(|| Some(viewer.id));
+    context.follow_loader.forget(viewer.id);
+    context.follow_loader.forget(user.id);
+    Ok(Profile::from(user))
 }
 
+
+fn role_change_from_entity(
+    pool: &crate::db::DbPool,
+    entity: super::db::RoleChangeEntity,
+) -> diesel::QueryResult<RoleChange> {
+    use super::db::get_user_by_id;
+    let username_of = |given_id: Option<i32>| -> diesel::QueryResult<Option<String>> {
+        match given_id {
+            Some(given_id) => Ok(Some(get_user_by_id(pool, &given_id)?.username)),
+            None => Ok(None),
+        }
+    };
+    Ok(RoleChange {
+        id: entity.id,
+        username: username_of(entity.target_id)?,
+        old_role: Role::parse(&entity.old_role),
+        new_role: Role::parse(&entity.new_role),
+        changed_by: username_of(entity.actor_id)?,
+        changed_at: entity.changed_at,
+    })
+}
+
+pub struct AdminQuery;
+
+#[juniper::graphql_object(Context = Context)]
+impl AdminQuery {
+    fn role_changes(
+        context: &Context,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<RoleChange>> {
+        let _span = tracing::info_span!("AdminQuery.roleChanges").entered();
+        let pool = &context.db_pool;
+        auth::require_role(pool, &context.token, Role::Admin)?;
+        use super::db::get_role_changes;
+        let role_changes = get_role_changes(
+            pool,
+            limit.unwrap_or(20) as i64,
+            offset.unwrap_or(0) as i64,
+        )
+        .map_err(internal_server_error)?;
+        role_changes
+            .into_iter()
+            .map(|entity| role_change_from_entity(pool, entity).map_err(internal_server_error))
+            .collect()
+    }
+}
+
+pub struct AdminMutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl AdminMutation {
+    fn set_user_role(context: &Context, username: String, role: Role) -> FieldResult<RoleChange> {
+        let _span = tracing::info_span!("AdminMutation.setUserRole", username = %username).entered();
+        let pool = &context.db_pool;
+        let viewer = auth::require_role(pool, &context.token, Role::Admin)?;
+
+        use super::db::get_user_by_username;
+        let user = match get_user_by_username(pool, &username) {
+            Ok(user) => user,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(UserError::NotFound.into_field_error())
+            }
+            Err(e) => return Err(internal_server_error(e)),
+        };
+        use super::db::set_role;
+        let role_change = set_role(pool, Some(viewer.id), &user.id, role.as_str())
+            .map_err(internal_server_error)?;
+        sessions::cache().forget_user(user.id);
+        tracing::info!(
+            actor_id = viewer.id,
+            user_id = user.id,
+            old_role = %role_change.old_role,
+            new_role = %role_change.new_role,
+            "role changed"
+        );
+        role_change_from_entity(pool, role_change).map_err(internal_server_error)
+    }
+}
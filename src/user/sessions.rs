@@ -0,0 +1,137 @@
+//! Sign-in sessions. Every token carries the id of the session it was issued
+//! for, so a session can be revoked from another device. Checking it on
+//! every request would cost a query, so live sessions are cached for a few
+//! seconds (`SESSION_CACHE_SECONDS`, 30 by default). Personal access tokens
+//! are cached the same way, by the hash of the token.
+//!
+//! The cache is per process: this process forgets a session or token as soon
+//! as it is revoked here, other replicas only once their entry expires.
+
+use super::model::{Role, Scope};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// What a sign-in is recorded with, to tell sessions apart.
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// The `User-Agent` header of a request, if it is valid text.
+pub fn user_agent(req: &actix_web::HttpRequest) -> Option<String> {
+    req.headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// A session known to be live, with what the token checks need from its user.
+#[derive(Clone, Copy)]
+pub struct CachedSession {
+    pub user_id: i32,
+    pub role: Role,
+}
+
+/// A personal access token known to be active, with what the token checks
+/// need from it.
+#[derive(Clone)]
+pub struct CachedToken {
+    pub token_id: i32,
+    pub user_id: i32,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+pub struct SessionCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<i32, (CachedSession, Instant)>>,
+    tokens: Mutex<HashMap<String, (CachedToken, Instant)>>,
+}
+
+/// Entries are only dropped when the map gets this big, keeping it bounded
+/// by the number of sessions active within the TTL.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+impl SessionCache {
+    pub fn new(ttl: Duration) -> Self {
+        SessionCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let seconds = std::env::var("SESSION_CACHE_SECONDS")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(30);
+        SessionCache::new(Duration::from_secs(seconds))
+    }
+
+    pub fn get(&self, session_id: i32) -> Option<CachedSession> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&session_id)
+            .filter(|(_, cached_at)| cached_at.elapsed() < self.ttl)
+            .map(|(session, _)| *session)
+    }
+
+    pub fn insert(&self, session_id: i32, session: CachedSession) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= PRUNE_THRESHOLD {
+            entries.retain(|_, (_, cached_at)| cached_at.elapsed() < self.ttl);
+        }
+        entries.insert(session_id, (session, Instant::now()));
+    }
+
+    pub fn forget(&self, session_id: i32) {
+        self.entries.lock().unwrap().remove(&session_id);
+    }
+
+    /// The token with this hash, unless it expired since it was cached.
+    pub fn get_token(&self, token_hash: &str) -> Option<CachedToken> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .get(token_hash)
+            .filter(|(_, cached_at)| cached_at.elapsed() < self.ttl)
+            .filter(|(token, _)| token.expires_at.is_none_or(|expires_at| expires_at > Utc::now()))
+            .map(|(token, _)| token.clone())
+    }
+
+    pub fn insert_token(&self, token_hash: String, token: CachedToken) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.len() >= PRUNE_THRESHOLD {
+            tokens.retain(|_, (_, cached_at)| cached_at.elapsed() < self.ttl);
+        }
+        tokens.insert(token_hash, (token, Instant::now()));
+    }
+
+    pub fn forget_token(&self, token_id: i32) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|_, (token, _)| token.token_id != token_id);
+    }
+
+    /// Drops every session and token of a user, after a change that affects
+    /// them all (password reset, new role, deleted account...).
+    pub fn forget_user(&self, user_id: i32) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, (session, _)| session.user_id != user_id);
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|_, (token, _)| token.user_id != user_id);
+    }
+}
+
+static CACHE: LazyLock<SessionCache> = LazyLock::new(SessionCache::from_env);
+
+pub fn cache() -> &'static SessionCache {
+    &CACHE
+}